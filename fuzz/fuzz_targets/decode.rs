@@ -0,0 +1,31 @@
+// Copyright © 2016 - Samuel Dolt <samuel@dolt.ch>
+//
+// See the COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Decode every structure from arbitrary bytes, as a bootloader would
+
+#![no_main]
+
+use dfuse::codec::SliceReader;
+use dfuse::{Image, ImageElement, Prefix, Suffix, TargetPrefix};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let reader = SliceReader::new(data);
+    let _ = Prefix::decode(&mut reader.clone());
+    let _ = TargetPrefix::decode(&mut reader.clone());
+    let _ = ImageElement::decode(&mut reader.clone());
+
+    let mut reader = reader;
+    if Image::decode(&mut reader).is_ok() {
+        assert!(reader.position() >= TargetPrefix::size());
+    }
+
+    let _ = Suffix::decode(data);
+});
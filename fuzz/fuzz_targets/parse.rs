@@ -0,0 +1,56 @@
+// Copyright © 2016 - Samuel Dolt <samuel@dolt.ch>
+//
+// See the COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Read arbitrary bytes as a file, with the stream and the slice readers
+//!
+//! Neither may panic, whatever the prefix claims, and both must agree, with
+//! or without limits. A file read back is written with `size()` bytes and can
+//! be read again.
+
+#![no_main]
+
+use dfuse::{DfuseFile, ParseLimits};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let streamed = DfuseFile::read_with_crc(&mut &data[..]);
+    let decoded = DfuseFile::from_bytes(data);
+    assert_eq!(streamed.is_ok(), decoded.is_ok());
+
+    let limits = ParseLimits::new()
+        .max_size(0x10000)
+        .max_targets(4)
+        .max_elements(16)
+        .max_element_size(0x1000);
+    assert_eq!(DfuseFile::read_limited(&mut &data[..], &limits).is_ok(),
+               DfuseFile::from_bytes_limited(data, &limits).is_ok());
+
+    let (file, _) = match (streamed, decoded) {
+        (Ok(streamed), Ok(decoded)) => {
+            assert_eq!(streamed, decoded);
+            decoded
+        }
+        _ => return,
+    };
+
+    let mut buf = Vec::new();
+    file.write_to(&mut buf).unwrap();
+    assert_eq!(buf.len(), file.size());
+
+    // Names may change, a name which isn't UTF-8 being read lossily
+    let (read, crc) = DfuseFile::from_bytes(&buf).unwrap();
+    assert!(crc.is_valid());
+    assert_eq!(read.suffix(), file.suffix());
+    assert_eq!(read.images().len(), file.images().len());
+    for (read, image) in read.images().iter().zip(file.images()) {
+        assert_eq!(read.alternate, image.alternate);
+        assert_eq!(read.elements, image.elements);
+    }
+});
@@ -0,0 +1,378 @@
+// Copyright © 2016 - Samuel Dolt <samuel@dolt.ch>
+//
+// See the COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Writing and reading over `AsyncWrite` and `AsyncRead`, available with the
+//! `async` feature
+//!
+//! Element data is written straight from the file, and the CRC is computed on
+//! the bytes the writer accepts, like `DfuseFile::write_to` does. Reading
+//! gathers the file in memory, then decodes it.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate dfuse;
+//! extern crate futures;
+//!
+//! use dfuse::DfuseFile;
+//! use futures::executor::block_on;
+//!
+//! # fn main() {
+//! let mut file = DfuseFile::new();
+//! file.add_image("Internal Flash", 0, 0x08000000, vec![0x01, 0x02, 0x03]);
+//!
+//! let mut buf = Vec::new();
+//! block_on(file.write_to_async(&mut buf)).unwrap();
+//! assert_eq!(buf.len(), file.size());
+//!
+//! let read = block_on(DfuseFile::read_from_async(&mut &buf[..])).unwrap();
+//! assert_eq!(read, file);
+//! # }
+//! ```
+
+use std::borrow::Cow;
+use std::future::Future;
+use std::io::{self, Error, ErrorKind};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::io::{AsyncRead, AsyncWrite};
+
+use ::codec::{CodecError, Limit, ParseLimits};
+use ::file::{CrcCheck, DfuseFile};
+use ::tools::CRC;
+
+/// Bytes asked to the reader at once
+const CHUNK_SIZE: usize = 4096;
+
+/// Future returned by `DfuseFile::write_to_async`
+pub struct WriteToAsync<'a, W: ?Sized + 'a> {
+    writer: &'a mut W,
+    /// Headers are encoded up front, element data is borrowed from the file
+    segments: Vec<Cow<'a, [u8]>>,
+    segment: usize,
+    pos: usize,
+    crc: CRC,
+    /// `true` once the CRC is the last segment
+    crc_done: bool,
+    error: Option<io::Error>,
+}
+
+impl<'a, W: ?Sized> WriteToAsync<'a, W> {
+    fn new(file: &'a DfuseFile, writer: &'a mut W) -> WriteToAsync<'a, W> {
+        let mut segments = Vec::new();
+        let mut headers = Vec::new();
+
+        let mut error = None;
+        match file.prefix() {
+            Some(prefix) => prefix.encode(&mut headers),
+            None => error = Some(Error::new(ErrorKind::InvalidInput, "more than 255 targets")),
+        }
+        for image in file.images() {
+            image.target_prefix().encode(&mut headers);
+
+            for element in &image.elements {
+                headers.extend_from_slice(&element.header());
+                segments.push(Cow::Owned(headers.split_off(0)));
+                segments.push(Cow::Borrowed(&element.data[..]));
+            }
+        }
+        if let Err(err) = file.suffix().encode(&mut headers) {
+            error = error.or_else(|| Some(Error::new(ErrorKind::InvalidInput, err)));
+        }
+        segments.push(Cow::Owned(headers));
+
+        WriteToAsync {
+            writer,
+            segments,
+            segment: 0,
+            pos: 0,
+            crc: CRC::new_jam(),
+            crc_done: false,
+            error,
+        }
+    }
+}
+
+impl<'a, W: AsyncWrite + Unpin + ?Sized> Future for WriteToAsync<'a, W> {
+    type Output = io::Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        if let Some(err) = this.error.take() {
+            return Poll::Ready(Err(err));
+        }
+
+        loop {
+            if this.segment == this.segments.len() {
+                if this.crc_done {
+                    return Pin::new(&mut *this.writer).poll_flush(cx);
+                }
+                // CRC is documented in the suffix section as a little endian 32bit unsigned integer
+                this.segments.push(Cow::Owned(this.crc.value().to_le_bytes().to_vec()));
+                this.crc_done = true;
+                continue;
+            }
+
+            let data = &this.segments[this.segment][this.pos..];
+            if data.is_empty() {
+                this.segment += 1;
+                this.pos = 0;
+                continue;
+            }
+
+            let n = match Pin::new(&mut *this.writer).poll_write(cx, data) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(res) => res?,
+            };
+            if n == 0 {
+                return Poll::Ready(Err(Error::new(ErrorKind::WriteZero, "failed to write the whole file")));
+            }
+            if !this.crc_done {
+                this.crc.update(&data[..n]);
+            }
+            this.pos += n;
+        }
+    }
+}
+
+/// Future returned by `DfuseFile::read_with_crc_async`
+pub struct ReadWithCrcAsync<'a, R: ?Sized + 'a> {
+    reader: &'a mut R,
+    buf: Vec<u8>,
+    limits: ParseLimits,
+}
+
+impl<'a, R: ?Sized> ReadWithCrcAsync<'a, R> {
+    /// Check `limits` while reading, no more than the maximum size being
+    /// gathered
+    pub fn limits(mut self, limits: ParseLimits) -> ReadWithCrcAsync<'a, R> {
+        self.limits = limits;
+        self
+    }
+}
+
+impl<'a, R: AsyncRead + Unpin + ?Sized> Future for ReadWithCrcAsync<'a, R> {
+    type Output = io::Result<(DfuseFile, CrcCheck)>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        loop {
+            // One byte more than allowed tells a file too large
+            let len = this.buf.len();
+            if len > this.limits.max_size {
+                return Poll::Ready(Err(CodecError::LimitExceeded(Limit::Size, this.limits.max_size).into()));
+            }
+            this.buf.resize(len + CHUNK_SIZE.min((this.limits.max_size - len).saturating_add(1)), 0);
+            let res = Pin::new(&mut *this.reader).poll_read(cx, &mut this.buf[len..]);
+            this.buf.truncate(len + match res {
+                Poll::Ready(Ok(n)) => n,
+                _ => 0,
+            });
+
+            match res {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Ok(0)) => {
+                    let res = DfuseFile::from_bytes_limited(&this.buf, &this.limits);
+                    return Poll::Ready(res.map_err(io::Error::from));
+                }
+                Poll::Ready(Ok(_)) => {}
+                Poll::Ready(Err(ref err)) if err.kind() == ErrorKind::Interrupted => {}
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+            }
+        }
+    }
+}
+
+/// Future returned by `DfuseFile::read_from_async`
+pub struct ReadFromAsync<'a, R: ?Sized + 'a>(ReadWithCrcAsync<'a, R>);
+
+impl<'a, R: ?Sized> ReadFromAsync<'a, R> {
+    /// Check `limits` while reading, see `ReadWithCrcAsync::limits`
+    pub fn limits(self, limits: ParseLimits) -> ReadFromAsync<'a, R> {
+        ReadFromAsync(self.0.limits(limits))
+    }
+}
+
+impl<'a, R: AsyncRead + Unpin + ?Sized> Future for ReadFromAsync<'a, R> {
+    type Output = io::Result<DfuseFile>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<DfuseFile>> {
+        match Pin::new(&mut self.0).poll(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+            Poll::Ready(Ok((_, crc))) if !crc.is_valid() => {
+                Poll::Ready(Err(Error::new(ErrorKind::InvalidData, "CRC mismatch")))
+            }
+            Poll::Ready(Ok((file, _))) => Poll::Ready(Ok(file)),
+        }
+    }
+}
+
+impl DfuseFile {
+    /// Write this file to `buf` without blocking, see `write_to`
+    pub fn write_to_async<'a, W>(&'a self, buf: &'a mut W) -> WriteToAsync<'a, W>
+        where W: AsyncWrite + Unpin + ?Sized
+    {
+        WriteToAsync::new(self, buf)
+    }
+
+    /// Read a `DfuseFile` without blocking, checking its CRC
+    pub fn read_from_async<R>(buf: &mut R) -> ReadFromAsync<'_, R>
+        where R: AsyncRead + Unpin + ?Sized
+    {
+        ReadFromAsync(DfuseFile::read_with_crc_async(buf))
+    }
+
+    /// Read a `DfuseFile` without blocking nor rejecting a bad CRC
+    pub fn read_with_crc_async<R>(buf: &mut R) -> ReadWithCrcAsync<'_, R>
+        where R: AsyncRead + Unpin + ?Sized
+    {
+        ReadWithCrcAsync {
+            reader: buf,
+            buf: Vec::new(),
+            limits: ParseLimits::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    /// Accept a few bytes at a time, every other call being pending
+    struct Trickle {
+        data: Vec<u8>,
+        pos: usize,
+        pending: bool,
+    }
+
+    impl Trickle {
+        fn new(data: Vec<u8>) -> Trickle {
+            Trickle {
+                data,
+                pos: 0,
+                pending: false,
+            }
+        }
+
+        fn ready(&mut self, cx: &mut Context) -> bool {
+            self.pending = !self.pending;
+            if self.pending {
+                cx.waker().wake_by_ref();
+            }
+            !self.pending
+        }
+    }
+
+    impl AsyncWrite for Trickle {
+        fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+            if !self.ready(cx) {
+                return Poll::Pending;
+            }
+            let n = buf.len().min(7);
+            self.data.extend_from_slice(&buf[..n]);
+            Poll::Ready(Ok(n))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncRead for Trickle {
+        fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+            if !self.ready(cx) {
+                return Poll::Pending;
+            }
+            let n = buf.len().min(self.data.len() - self.pos).min(300);
+            buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+            self.pos += n;
+            Poll::Ready(Ok(n))
+        }
+    }
+
+    fn file() -> DfuseFile {
+        let mut file = DfuseFile::new();
+        file.add_image("ST...", 0, 0x08000000, vec![0x01; 0x2000]);
+        file.add_unamed_image(1, 0x1FFFF800, vec![0xAA, 0x55]);
+        file.set_vendor_id(0x0483);
+        file.suffix_mut().vendor_data = vec![0x01, 0x02, 0x03];
+        file
+    }
+
+    #[test]
+    fn write_and_read_back() {
+        let file = file();
+        let mut writer = Trickle::new(Vec::new());
+        block_on(file.write_to_async(&mut writer)).unwrap();
+
+        let mut buf = vec![];
+        file.write_to(&mut buf).unwrap();
+        assert_eq!(writer.data, buf);
+
+        let mut reader = Trickle::new(buf);
+        assert_eq!(block_on(DfuseFile::read_from_async(&mut reader)).unwrap(), file);
+    }
+
+    #[test]
+    fn read_reject_bad_crc() {
+        let mut buf = vec![];
+        file().write_to(&mut buf).unwrap();
+        let last = buf.len() - 1;
+        buf[last] ^= 0xFF;
+
+        assert!(block_on(DfuseFile::read_from_async(&mut &buf[..])).is_err());
+        let (read, crc) = block_on(DfuseFile::read_with_crc_async(&mut &buf[..])).unwrap();
+        assert_eq!(read, file());
+        assert!(!crc.is_valid());
+
+        assert!(block_on(DfuseFile::read_from_async(&mut &buf[..last])).is_err());
+    }
+
+    #[test]
+    fn read_within_limits() {
+        let mut buf = vec![];
+        file().write_to(&mut buf).unwrap();
+
+        let read = |limits: ParseLimits| block_on(DfuseFile::read_from_async(&mut Trickle::new(buf.clone())).limits(limits));
+        assert_eq!(read(ParseLimits::new().max_size(buf.len())).unwrap(), file());
+        assert_eq!(read(ParseLimits::new().max_size(buf.len() - 1)).unwrap_err().to_string(),
+                   CodecError::LimitExceeded(Limit::Size, buf.len() - 1).to_string());
+        assert!(read(ParseLimits::new().max_element_size(0x1000)).is_err());
+    }
+
+    #[test]
+    fn write_reject_big_suffix() {
+        let mut file = file();
+        file.suffix_mut().vendor_data = vec![0; 0x100];
+
+        let mut buf = Vec::new();
+        assert_eq!(block_on(file.write_to_async(&mut buf)).unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn write_reject_too_many_targets() {
+        let mut file = file();
+        for _ in 0..255 {
+            file.add_unamed_image(0, 0x08000000, vec![0x00]);
+        }
+
+        let mut buf = Vec::new();
+        assert_eq!(block_on(file.write_to_async(&mut buf)).unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert!(buf.is_empty());
+    }
+}
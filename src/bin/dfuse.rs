@@ -0,0 +1,231 @@
+// Copyright © 2016 - Samuel Dolt <samuel@dolt.ch>
+//
+// See the COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Command line tool to inspect `DfuSe` files
+
+extern crate dfuse;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::process;
+
+use dfuse::{BcdVersion, DfuseFile, Image};
+
+const USAGE: &str = "Usage:
+    dfuse info [--json] [--hexdump <width>] <file.dfu>
+    dfuse diff <old.dfu> <new.dfu>
+    dfuse suffix [--vid <id>] [--pid <id>] [--version <major.minor[.patch]>]
+                 <file.dfu> [<output>]
+    dfuse extract [--flat] [--fill <byte>] [--base <addr>] [--end <addr>]
+                  <file.dfu> <alternate> <output>";
+
+/// Parse a decimal or `0x` prefixed hexadecimal number
+fn parse_number(txt: &str) -> Result<u64, String> {
+    let res = if txt.starts_with("0x") || txt.starts_with("0X") {
+        u64::from_str_radix(&txt[2..], 16)
+    } else {
+        txt.parse()
+    };
+    res.map_err(|_| format!("invalid number: {}", txt))
+}
+
+fn parse_bounded(txt: &str, max: u64) -> Result<u64, String> {
+    let value = parse_number(txt)?;
+    if value > max {
+        return Err(format!("out of range: {}", txt));
+    }
+    Ok(value)
+}
+
+fn open(path: &str) -> Result<DfuseFile, String> {
+    let mut file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    DfuseFile::read_from(&mut file).map_err(|e| format!("{}: {}", path, e))
+}
+
+/// Print a report of a file, even one with a bad CRC, as text or JSON
+fn info(args: &[String]) -> Result<i32, String> {
+    let mut json = false;
+    let mut hexdump = None;
+    let mut positionals = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| USAGE.to_string());
+        match arg.as_str() {
+            "--json" => json = true,
+            "--hexdump" => hexdump = Some(parse_bounded(value()?, 256)? as usize),
+            _ => positionals.push(arg),
+        }
+    }
+
+    if positionals.len() != 1 {
+        return Err(USAGE.to_string());
+    }
+
+    let path = positionals[0];
+    let (file, crc) = File::open(path)
+        .and_then(|mut f| DfuseFile::read_with_crc(&mut f))
+        .map_err(|e| format!("{}: {}", path, e))?;
+
+    let mut report = file.report().crc(crc);
+    if let Some(width) = hexdump {
+        report = report.hexdump(width);
+    }
+    if json {
+        print!("{}", report.to_json());
+    } else {
+        print!("{}", report);
+    }
+
+    Ok(0)
+}
+
+/// Print the differences, exit with 1 if the files differ like `diff` does
+fn diff(args: &[String]) -> Result<i32, String> {
+    if args.len() != 2 {
+        return Err(USAGE.to_string());
+    }
+
+    let old = open(&args[0])?;
+    let new = open(&args[1])?;
+
+    let diff = old.diff(&new);
+    print!("{}", diff);
+
+    Ok(if diff.is_empty() { 0 } else { 1 })
+}
+
+/// Change the suffix of a file, in place unless an output is given
+fn suffix(args: &[String]) -> Result<i32, String> {
+    let mut vendor_id = None;
+    let mut product_id = None;
+    let mut version = None;
+    let mut positionals = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| USAGE.to_string());
+        match arg.as_str() {
+            "--vid" => vendor_id = Some(parse_bounded(value()?, 0xFFFF)? as u16),
+            "--pid" => product_id = Some(parse_bounded(value()?, 0xFFFF)? as u16),
+            "--version" => {
+                let txt = value()?;
+                version = Some(txt.parse::<BcdVersion>().map_err(|e| format!("{}: {}", txt, e))?);
+            }
+            _ => positionals.push(arg),
+        }
+    }
+
+    if positionals.is_empty() || positionals.len() > 2 {
+        return Err(USAGE.to_string());
+    }
+
+    let mut file = open(positionals[0])?;
+    if let Some(vid) = vendor_id {
+        file.set_vendor_id(vid);
+    }
+    if let Some(pid) = product_id {
+        file.set_product_id(pid);
+    }
+    if let Some(version) = version {
+        file.set_bcd_version(version);
+    }
+
+    let output = positionals.get(1).unwrap_or(&positionals[0]);
+    let mut buf = Vec::new();
+    file.write_to(&mut buf)
+        .and_then(|_| fs::write(output, &buf))
+        .map_err(|e| format!("{}: {}", output, e))?;
+
+    Ok(0)
+}
+
+/// Every element of the targets using `alternate`
+fn target(file: &DfuseFile, alternate: u8) -> Result<Image, String> {
+    let images: Vec<&Image> = file.images().iter().filter(|i| i.alternate == alternate).collect();
+    if images.is_empty() {
+        return Err(format!("no target for alternate {}", alternate));
+    }
+
+    Ok(Image {
+        name: images[0].name.clone(),
+        alternate,
+        elements: images.iter().flat_map(|i| i.elements.iter().cloned()).collect(),
+    })
+}
+
+/// Write the data of a target, as a flat binary or as one file per element
+fn extract(args: &[String]) -> Result<i32, String> {
+    let mut flat = false;
+    let mut fill = 0xFF;
+    let mut base = None;
+    let mut end = None;
+    let mut positionals = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| USAGE.to_string());
+        match arg.as_str() {
+            "--flat" => flat = true,
+            "--fill" => fill = parse_bounded(value()?, 0xFF)? as u8,
+            "--base" => base = Some(parse_bounded(value()?, 0xFFFFFFFF)? as u32),
+            "--end" => end = Some(parse_bounded(value()?, 0x100000000)?),
+            _ => positionals.push(arg),
+        }
+    }
+
+    if positionals.len() != 3 {
+        return Err(USAGE.to_string());
+    }
+
+    let file = open(positionals[0])?;
+    let alternate = parse_bounded(positionals[1], 0xFF)? as u8;
+    let output = positionals[2];
+    let image = target(&file, alternate)?;
+
+    if flat {
+        let (start, stop) = image.bounds().unwrap_or((0, 0));
+        let base = base.unwrap_or(start);
+        let end = end.unwrap_or(stop);
+
+        let buf = image.flatten(base, end, fill).map_err(|e| format!("{}: {}", output, e))?;
+        fs::write(output, &buf).map_err(|e| format!("{}: {}", output, e))?;
+    } else {
+        for element in &image.elements {
+            let path = format!("{}-0x{:08X}.bin", output, element.start_adress);
+            File::create(&path)
+                .and_then(|mut out| out.write_all(&element.data))
+                .map_err(|e| format!("{}: {}", path, e))?;
+        }
+    }
+
+    Ok(0)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let res = match args.first().map(|s| s.as_str()) {
+        Some("info") => info(&args[1..]),
+        Some("diff") => diff(&args[1..]),
+        Some("suffix") => suffix(&args[1..]),
+        Some("extract") => extract(&args[1..]),
+        _ => Err(USAGE.to_string()),
+    };
+
+    match res {
+        Ok(code) => process::exit(code),
+        Err(msg) => {
+            eprintln!("{}", msg);
+            process::exit(2);
+        }
+    }
+}
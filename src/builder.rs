@@ -0,0 +1,200 @@
+// Copyright © 2016 - Samuel Dolt <samuel@dolt.ch>
+//
+// See the COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use ::elements::{Image, ImageElement, Prefix, Suffix};
+use ::error::{Error, Result};
+use ::file::DfuseFile;
+use ::version::BcdVersion;
+
+/// Build a `DfuseFile` whose suffix is always given
+///
+/// Vendor ID, product ID, version and at least one image are required,
+/// `build` failing with `Error::MissingField` otherwise.
+///
+/// # Examples
+///
+/// ```
+/// use dfuse::{BcdVersion, DfuseFile};
+///
+/// let file = DfuseFile::builder()
+///     .vendor_id(0x0483)
+///     .product_id(0xDF11)
+///     .version(BcdVersion::new(2, 2, 0).unwrap())
+///     .image("Internal Flash", 0, 0x08000000, vec![0x01, 0x02, 0x03])
+///     .build()
+///     .unwrap();
+/// assert_eq!(file.suffix().fw_version, 0x0220);
+///
+/// assert!(DfuseFile::builder().vendor_id(0x0483).build().is_err());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct DfuseFileBuilder {
+    vendor_id: Option<u16>,
+    product_id: Option<u16>,
+    version: Option<BcdVersion>,
+    vendor_data: Vec<u8>,
+    images: Vec<Image>,
+}
+
+impl DfuseFileBuilder {
+    pub fn new() -> DfuseFileBuilder {
+        DfuseFileBuilder::default()
+    }
+
+    pub fn vendor_id(mut self, vid: u16) -> DfuseFileBuilder {
+        self.vendor_id = Some(vid);
+        self
+    }
+
+    pub fn product_id(mut self, pid: u16) -> DfuseFileBuilder {
+        self.product_id = Some(pid);
+        self
+    }
+
+    pub fn version(mut self, version: BcdVersion) -> DfuseFileBuilder {
+        self.version = Some(version);
+        self
+    }
+
+    /// Vendor specific bytes of the suffix
+    pub fn vendor_data(mut self, data: Vec<u8>) -> DfuseFileBuilder {
+        self.vendor_data = data;
+        self
+    }
+
+    /// Add a named image holding `data` at `start_adress`
+    pub fn image(self, name: &str, alternate: u8, start_adress: u32, data: Vec<u8>) -> DfuseFileBuilder {
+        self.target(Image {
+            name: Some(name.to_string()),
+            alternate,
+            elements: vec![ImageElement::new(start_adress, data)],
+        })
+    }
+
+    /// Add an image, as is
+    pub fn target(mut self, image: Image) -> DfuseFileBuilder {
+        self.images.push(image);
+        self
+    }
+
+    /// Add images, in order
+    pub fn targets<I: IntoIterator<Item = Image>>(mut self, images: I) -> DfuseFileBuilder {
+        self.images.extend(images);
+        self
+    }
+
+    /// Check everything and build the file
+    ///
+    /// Fail with `Error::Overlap` if elements of a target overlap, and with
+    /// `Error::InvalidFile` if the file can't be written.
+    pub fn build(self) -> Result<DfuseFile> {
+        let vendor_id = self.vendor_id.ok_or(Error::MissingField("vendor id"))?;
+        let product_id = self.product_id.ok_or(Error::MissingField("product id"))?;
+        let version = self.version.ok_or(Error::MissingField("version"))?;
+        if self.images.is_empty() {
+            return Err(Error::MissingField("image"));
+        }
+
+        if self.images.len() > u8::MAX as usize {
+            return Err(Error::InvalidFile("more than 255 targets"));
+        }
+        for image in &self.images {
+            if let Some(adress) = image.find_overlap() {
+                return Err(Error::Overlap {
+                    alternate: image.alternate,
+                    adress,
+                });
+            }
+        }
+
+        let suffix = Suffix {
+            fw_version: version.to_bcd(),
+            usb_pid: product_id,
+            usb_vid: vendor_id,
+            vendor_data: self.vendor_data,
+        };
+        if suffix.size() + 4 > Suffix::MAX_LENGTH {
+            return Err(Error::InvalidFile("too much DFU suffix vendor data"));
+        }
+
+        let size = self.images.iter().fold(Prefix::size() + suffix.size() + 4, |sum, x| sum + x.size());
+        if size > u32::MAX as usize {
+            return Err(Error::InvalidFile("file larger than 4 GiB"));
+        }
+
+        let mut file = DfuseFile::new();
+        *file.images_mut() = self.images;
+        *file.suffix_mut() = suffix;
+        Ok(file)
+    }
+}
+
+impl DfuseFile {
+    /// Start building a file, see `DfuseFileBuilder`
+    pub fn builder() -> DfuseFileBuilder {
+        DfuseFileBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn builder() -> DfuseFileBuilder {
+        DfuseFile::builder()
+            .vendor_id(0x0483)
+            .product_id(0xDF11)
+            .version(BcdVersion::new(2, 2, 0).unwrap())
+            .image("Internal Flash", 0, 0x08000000, vec![0x00; 0x10])
+    }
+
+    #[test]
+    fn build_file() {
+        let file = builder()
+            .vendor_data(vec![0x42])
+            .targets(vec![Image {
+                              name: None,
+                              alternate: 1,
+                              elements: vec![ImageElement::new(0x1FFFF800, vec![0xAA, 0x55])],
+                          }])
+            .build()
+            .unwrap();
+
+        let mut expected = DfuseFile::new();
+        expected.add_image("Internal Flash", 0, 0x08000000, vec![0x00; 0x10]);
+        expected.add_unamed_image(1, 0x1FFFF800, vec![0xAA, 0x55]);
+        expected.set_vendor_id(0x0483);
+        expected.set_product_id(0xDF11);
+        expected.set_version(0x0220);
+        expected.suffix_mut().vendor_data = vec![0x42];
+        assert_eq!(file, expected);
+    }
+
+    #[test]
+    fn reject_missing_and_invalid() {
+        let missing = |builder: DfuseFileBuilder| match builder.build() {
+            Err(Error::MissingField(field)) => field,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(missing(DfuseFile::builder()), "vendor id");
+        assert_eq!(missing(DfuseFile::builder().vendor_id(0x0483).product_id(0xDF11)), "version");
+
+        let overlap = builder().target(Image {
+            name: None,
+            alternate: 0,
+            elements: vec![ImageElement::new(0x08000000, vec![0x00; 4]), ImageElement::new(0x08000002, vec![0x00])],
+        });
+        assert!(overlap.build().is_err());
+        assert!(builder().vendor_data(vec![0x00; 0x100]).build().is_err());
+
+        let too_many = (0..256).fold(builder(), |b, n| b.image("x", 0, n * 0x10, vec![]));
+        assert!(too_many.build().is_err());
+    }
+}
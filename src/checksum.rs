@@ -0,0 +1,276 @@
+// Copyright © 2016 - Samuel Dolt <samuel@dolt.ch>
+//
+// See the COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! CRC engine and application checksum patching
+//!
+//! `CRC` is a table driven engine for any CRC from 8 to 32 bits, `CRCParams`
+//! holds its parameters and the usual presets. `WriterWithCRC` computes the
+//! CRC of everything written through it.
+//!
+//! # Examples
+//!
+//! ```
+//! use dfuse::checksum::CRCParams;
+//!
+//! assert_eq!(CRCParams::CRC_32_BZIP2.checksum(b"123456789"), 0xFC891918);
+//! assert_eq!(CRCParams::CRC_16_CCITT.checksum(b"123456789"), 0x2189);
+//! ```
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use ::elements::Image;
+use ::error::{Error, Result};
+use ::flash::ERASED;
+pub use ::tools::{CRC, CRCParams, WriterWithCRC};
+
+/// CRC computed by `Image::patch_checksum`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrcAlgorithm {
+    /// The usual CRC-32 (ISO-HDLC), as used by zlib
+    Crc32,
+    /// The STM32 CRC unit with its reset configuration
+    ///
+    /// The data is fed as little endian 32 bit words, the range must be a
+    /// multiple of 4 bytes long.
+    Stm32,
+    /// Any other CRC-32, fed byte by byte
+    Custom {
+        poly: u32,
+        ref_in: bool,
+        ref_out: bool,
+        initial: u32,
+        xor_out: u32,
+    },
+    /// Any CRC of 8 to 32 bits, fed byte by byte
+    ///
+    /// The CRC is stored on `width / 8` bytes, rounded up.
+    /// `Image::patch_checksum` fails with `Error::UnsupportedCrcWidth` for
+    /// other widths.
+    Params(CRCParams),
+}
+
+impl CrcAlgorithm {
+    fn params(&self) -> CRCParams {
+        match *self {
+            CrcAlgorithm::Crc32 => CRCParams::CRC_32_ISO_HDLC,
+            CrcAlgorithm::Stm32 => CRCParams::STM32,
+            CrcAlgorithm::Custom { poly, ref_in, ref_out, initial, xor_out } => {
+                CRCParams {
+                    width: 32,
+                    poly,
+                    ref_in,
+                    ref_out,
+                    initial,
+                    xor_out,
+                }
+            }
+            CrcAlgorithm::Params(params) => params,
+        }
+    }
+
+    fn engine(&self) -> Result<CRC> {
+        let params = self.params();
+        CRC::try_new(params).ok_or(Error::UnsupportedCrcWidth(params.width))
+    }
+
+    /// Number of bytes the CRC is stored on
+    fn size(&self) -> usize {
+        (self.params().width as usize).div_ceil(8)
+    }
+}
+
+/// Range to check and where to store its CRC
+#[derive(Debug, Clone)]
+pub struct ChecksumPatch {
+    start: u32,
+    end: u64,
+    adress: u32,
+    algorithm: CrcAlgorithm,
+    fill: u8,
+}
+
+impl ChecksumPatch {
+    /// CRC-32 of `start` to `end` (excluded), stored little endian at `adress`
+    ///
+    /// Bytes not covered by an element are `0xFF`.
+    pub fn new(start: u32, end: u64, adress: u32) -> ChecksumPatch {
+        ChecksumPatch {
+            start,
+            end,
+            adress,
+            algorithm: CrcAlgorithm::Crc32,
+            fill: ERASED,
+        }
+    }
+
+    pub fn algorithm(mut self, algorithm: CrcAlgorithm) -> ChecksumPatch {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Value of the bytes not covered by an element
+    pub fn fill(mut self, fill: u8) -> ChecksumPatch {
+        self.fill = fill;
+        self
+    }
+}
+
+impl Image {
+    /// Compute a CRC over a range and write it in this image
+    ///
+    /// An element covering the CRC address is patched, otherwise an element
+    /// is extended or added. Elements the CRC ends up touching are joined
+    /// into one. Return the CRC written.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dfuse::{ChecksumPatch, CrcAlgorithm, Image, ImageElement};
+    ///
+    /// let mut image = Image {
+    ///     name: None,
+    ///     alternate: 0,
+    ///     elements: vec![ImageElement::new(0x08000000, vec![0x78, 0x56, 0x34, 0x12])],
+    /// };
+    ///
+    /// let patch = ChecksumPatch::new(0x08000000, 0x08000004, 0x08000004)
+    ///     .algorithm(CrcAlgorithm::Stm32);
+    /// assert_eq!(image.patch_checksum(&patch).unwrap(), 0xDF8A8A2B);
+    /// assert_eq!(image.elements[0].data.len(), 8);
+    /// ```
+    pub fn patch_checksum(&mut self, patch: &ChecksumPatch) -> Result<u32> {
+        let mut crc = patch.algorithm.engine()?;
+        let size = patch.algorithm.size();
+        let crc_end = patch.adress as u64 + size as u64;
+        if crc_end > 0x100000000 {
+            return Err(Error::InvalidRange {
+                start: patch.adress,
+                end: crc_end,
+            });
+        }
+        if (patch.adress as u64) < patch.end && (patch.start as u64) < crc_end {
+            return Err(Error::Overlap {
+                alternate: self.alternate,
+                adress: patch.adress.max(patch.start),
+            });
+        }
+
+        let data = self.flatten(patch.start, patch.end, patch.fill)?;
+
+        match patch.algorithm {
+            CrcAlgorithm::Stm32 => {
+                if data.len() % 4 != 0 {
+                    return Err(Error::InvalidRange {
+                        start: patch.start,
+                        end: patch.end,
+                    });
+                }
+                for word in data.chunks(4) {
+                    crc.add_u32(LittleEndian::read_u32(word));
+                }
+            }
+            _ => crc.update(&data),
+        }
+
+        let value = crc.finalize();
+        self.patch_and_join(patch.adress, &value.to_le_bytes()[..size]);
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::elements::ImageElement;
+
+    fn image() -> Image {
+        Image {
+            name: None,
+            alternate: 0,
+            elements: vec![ImageElement::new(0x08000000, (0..0x20).collect())],
+        }
+    }
+
+    #[test]
+    fn patch_extend_element() {
+        let mut image = image();
+        let patch = ChecksumPatch::new(0x08000000, 0x08000040, 0x08000020);
+        assert!(image.patch_checksum(&patch).is_err());
+
+        let patch = ChecksumPatch::new(0x08000000, 0x08000020, 0x08000020);
+        // zlib.crc32(bytes(range(0x20)))
+        assert_eq!(image.patch_checksum(&patch).unwrap(), 0x91267E8A);
+        assert_eq!(image.elements.len(), 1);
+        assert_eq!(&image.elements[0].data[0x20..], &[0x8A, 0x7E, 0x26, 0x91]);
+    }
+
+    #[test]
+    fn patch_fill_gaps() {
+        let mut image = image();
+        let patch = ChecksumPatch::new(0x08000000, 0x08000040, 0x08000100);
+        // zlib.crc32(bytes(range(0x20)) + b"\xff" * 0x20)
+        assert_eq!(image.patch_checksum(&patch).unwrap(), 0x19C46364);
+        assert_eq!(image.elements[1], ImageElement::new(0x08000100, vec![0x64, 0x63, 0xC4, 0x19]));
+    }
+
+    #[test]
+    fn patch_stm32() {
+        let mut image = image();
+        image.elements[0].data.extend_from_slice(&(0x20..0x40).collect::<Vec<u8>>());
+        image.elements[0].data.extend_from_slice(&[0; 4]);
+
+        let patch = ChecksumPatch::new(0x08000000, 0x08000040, 0x08000040).algorithm(CrcAlgorithm::Stm32);
+        assert_eq!(image.patch_checksum(&patch).unwrap(), 0x1125C90E);
+        assert_eq!(&image.elements[0].data[0x40..], &[0x0E, 0xC9, 0x25, 0x11]);
+
+        let patch = ChecksumPatch::new(0x08000000, 0x08000003, 0x08000040).algorithm(CrcAlgorithm::Stm32);
+        assert!(image.patch_checksum(&patch).is_err());
+    }
+
+    #[test]
+    fn patch_custom() {
+        let jam = CrcAlgorithm::Custom {
+            poly: 0x04C11DB7,
+            ref_in: true,
+            ref_out: true,
+            initial: 0xFFFFFFFF,
+            xor_out: 0,
+        };
+        let mut image = image();
+        let patch = ChecksumPatch::new(0x08000000, 0x08000040, 0x08000100).algorithm(jam);
+        assert_eq!(image.patch_checksum(&patch).unwrap(), !0x19C46364);
+    }
+
+    #[test]
+    fn patch_params() {
+        let mut image = image();
+        let ccitt = CrcAlgorithm::Params(CRCParams::CRC_16_CCITT);
+        let patch = ChecksumPatch::new(0x08000000, 0x08000020, 0x08000200).algorithm(ccitt);
+        let value = image.patch_checksum(&patch).unwrap();
+        assert_eq!(value, CRCParams::CRC_16_CCITT.checksum(&(0..0x20).collect::<Vec<u8>>()));
+        assert_eq!(image.elements[1].data, &value.to_le_bytes()[..2]);
+
+        // The CRC-16 ends right where the range starts
+        let patch = ChecksumPatch::new(0x08000000, 0x08000020, 0x07FFFFFE).algorithm(ccitt);
+        assert_eq!(image.patch_checksum(&patch).unwrap(), value);
+        assert_eq!(image.elements[0].data[..4], [value as u8, (value >> 8) as u8, 0x00, 0x01]);
+
+        let odd = CrcAlgorithm::Params(CRCParams {
+            width: 5,
+            ..CRCParams::CRC_16_CCITT
+        });
+        let patch = ChecksumPatch::new(0x08000000, 0x08000020, 0x08000300).algorithm(odd);
+        match image.patch_checksum(&patch) {
+            Err(Error::UnsupportedCrcWidth(5)) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+        assert_eq!(image.elements.len(), 2);
+    }
+}
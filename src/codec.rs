@@ -0,0 +1,265 @@
+// Copyright © 2016 - Samuel Dolt <samuel@dolt.ch>
+//
+// See the COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Encoding and decoding in memory, with `core` and `alloc` only
+//!
+//! Without the default `std` feature, the crate builds under `#![no_std]`:
+//! a bootloader holding a `DfuSe` file in RAM decodes it with
+//! `DfuseFile::from_bytes`, or structure by structure with a `SliceReader`,
+//! and checks it with the `CRC` engine.
+//!
+//! Nothing links `std` then, which is checked by building for a bare metal
+//! target:
+//!
+//! ```text
+//! cargo build --no-default-features --target thumbv7m-none-eabi
+//! ```
+//!
+//! # Examples
+//!
+//! ```
+//! use dfuse::DfuseFile;
+//! use dfuse::codec::SliceReader;
+//! use dfuse::Prefix;
+//!
+//! let mut file = DfuseFile::new();
+//! file.add_image("Internal Flash", 0, 0x08000000, vec![0x01, 0x02, 0x03]);
+//! let buf = file.to_bytes().unwrap();
+//!
+//! let prefix = Prefix::decode(&mut SliceReader::new(&buf)).unwrap();
+//! assert_eq!(prefix.nb_images(), 1);
+//!
+//! let (read, crc) = DfuseFile::from_bytes(&buf).unwrap();
+//! assert!(crc.is_valid());
+//! assert_eq!(read, file);
+//! ```
+
+use core::fmt;
+use core::result;
+
+pub use ::tools::{CRC, CRCParams};
+
+/// Why some bytes can't be decoded or encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodecError {
+    /// The data end before the structure does
+    UnexpectedEnd,
+    /// The data don't hold a valid structure
+    Invalid(&'static str),
+    /// The data go beyond a `ParseLimits`, the limit being given
+    LimitExceeded(Limit, usize),
+}
+
+/// A limit of `ParseLimits`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Size,
+    Targets,
+    Elements,
+    ElementSize,
+}
+
+pub type Result<T> = result::Result<T, CodecError>;
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CodecError::UnexpectedEnd => write!(f, "unexpected end of data"),
+            CodecError::Invalid(msg) => write!(f, "{}", msg),
+            CodecError::LimitExceeded(Limit::Size, limit) => write!(f, "file larger than {} bytes", limit),
+            CodecError::LimitExceeded(Limit::Targets, limit) => write!(f, "more than {} targets", limit),
+            CodecError::LimitExceeded(Limit::Elements, limit) => {
+                write!(f, "target with more than {} elements", limit)
+            }
+            CodecError::LimitExceeded(Limit::ElementSize, limit) => {
+                write!(f, "element larger than {} bytes", limit)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl ::std::error::Error for CodecError {}
+
+#[cfg(feature = "std")]
+impl From<CodecError> for ::std::io::Error {
+    fn from(err: CodecError) -> ::std::io::Error {
+        use std::io::{Error, ErrorKind};
+
+        match err {
+            CodecError::UnexpectedEnd => Error::new(ErrorKind::UnexpectedEof, err),
+            CodecError::Invalid(_) |
+            CodecError::LimitExceeded(..) => Error::new(ErrorKind::InvalidData, err),
+        }
+    }
+}
+
+/// Limits checked while reading a file, to read untrusted files safely
+///
+/// Nothing is allocated from a count or a size read in the file before it is
+/// checked against the limits and the data left. By default there is no
+/// limit but the one of the format.
+///
+/// # Examples
+///
+/// ```
+/// use dfuse::DfuseFile;
+/// use dfuse::codec::{CodecError, Limit, ParseLimits};
+///
+/// let mut file = DfuseFile::new();
+/// file.add_image("Internal Flash", 0, 0x08000000, vec![0x00; 0x1000]);
+/// let buf = file.to_bytes().unwrap();
+///
+/// let limits = ParseLimits::new().max_element_size(0x400);
+/// assert_eq!(DfuseFile::from_bytes_limited(&buf, &limits).unwrap_err(),
+///            CodecError::LimitExceeded(Limit::ElementSize, 0x400));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseLimits {
+    pub(crate) max_size: usize,
+    max_targets: usize,
+    max_elements: usize,
+    max_element_size: usize,
+}
+
+impl Default for ParseLimits {
+    fn default() -> ParseLimits {
+        ParseLimits {
+            max_size: usize::MAX,
+            max_targets: usize::MAX,
+            max_elements: usize::MAX,
+            max_element_size: usize::MAX,
+        }
+    }
+}
+
+impl ParseLimits {
+    pub fn new() -> ParseLimits {
+        ParseLimits::default()
+    }
+
+    /// Size of the whole file, suffix and CRC included
+    pub fn max_size(mut self, size: usize) -> ParseLimits {
+        self.max_size = size;
+        self
+    }
+
+    pub fn max_targets(mut self, targets: usize) -> ParseLimits {
+        self.max_targets = targets;
+        self
+    }
+
+    /// Number of elements of a target
+    pub fn max_elements(mut self, elements: usize) -> ParseLimits {
+        self.max_elements = elements;
+        self
+    }
+
+    /// Size of the data of an element
+    pub fn max_element_size(mut self, size: usize) -> ParseLimits {
+        self.max_element_size = size;
+        self
+    }
+
+    pub(crate) fn check_size(&self, size: usize) -> Result<()> {
+        check(Limit::Size, size, self.max_size)
+    }
+
+    pub(crate) fn check_targets(&self, targets: usize) -> Result<()> {
+        check(Limit::Targets, targets, self.max_targets)
+    }
+
+    pub(crate) fn check_elements(&self, elements: usize) -> Result<()> {
+        check(Limit::Elements, elements, self.max_elements)
+    }
+
+    pub(crate) fn check_element_size(&self, size: usize) -> Result<()> {
+        check(Limit::ElementSize, size, self.max_element_size)
+    }
+}
+
+fn check(limit: Limit, value: usize, max: usize) -> Result<()> {
+    if value > max {
+        return Err(CodecError::LimitExceeded(limit, max));
+    }
+    Ok(())
+}
+
+/// Read little endian values from a byte slice
+#[derive(Debug, Clone)]
+pub struct SliceReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> SliceReader<'a> {
+    pub fn new(buf: &'a [u8]) -> SliceReader<'a> {
+        SliceReader {
+            buf,
+            pos: 0,
+        }
+    }
+
+    /// Number of bytes read so far
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// Bytes not read yet
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.buf[self.pos..]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos == self.buf.len()
+    }
+
+    /// The next `len` bytes, without copying them
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.buf.len() - self.pos < len {
+            return Err(CodecError::UnexpectedEnd);
+        }
+        let bytes = &self.buf[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16> {
+        let b = self.read_bytes(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32> {
+        let b = self.read_bytes(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_little_endian() {
+        let mut reader = SliceReader::new(&[0x01, 0x34, 0x12, 0x78, 0x56, 0x34, 0x12, 0xFF]);
+        assert_eq!(reader.read_u8(), Ok(0x01));
+        assert_eq!(reader.read_u16(), Ok(0x1234));
+        assert_eq!(reader.read_u32(), Ok(0x12345678));
+        assert_eq!(reader.position(), 7);
+        assert_eq!(reader.read_u16(), Err(CodecError::UnexpectedEnd));
+        assert_eq!(reader.remaining(), &[0xFF]);
+        assert_eq!(reader.read_bytes(1), Ok(&[0xFF][..]));
+        assert!(reader.is_empty());
+    }
+}
@@ -0,0 +1,202 @@
+// Copyright © 2016 - Samuel Dolt <samuel@dolt.ch>
+//
+// See the COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Delta patches between two firmware versions
+//!
+//! A delta is a `DfuseFile` holding only the erase sectors whose content
+//! differ between the old and the new file. Downloading it on a device
+//! holding the old firmware gives the same flash content as the new one.
+//!
+//! # Examples
+//!
+//! ```
+//! use dfuse::DfuseFile;
+//! use dfuse::flash::{FlashLayout, FlashMemory};
+//!
+//! let layout = FlashLayout::uniform(0x08000000, 4, 0x400);
+//!
+//! let mut old = DfuseFile::new();
+//! old.add_image("ST...", 0, 0x08000000, vec![0x00; 0x1000]);
+//! let mut new = DfuseFile::new();
+//! new.add_image("ST...", 0, 0x08000000, vec![0x00; 0x1000]);
+//! new.images_mut()[0].elements[0].data[0x500] = 0x42;
+//!
+//! let delta = DfuseFile::delta(&old, &new, &layout);
+//! assert_eq!(delta.images()[0].elements[0].start_adress, 0x08000400);
+//! assert_eq!(delta.images()[0].elements[0].data.len(), 0x400);
+//!
+//! let mut flash = FlashMemory::new(layout);
+//! flash.download_file(&old, 0).unwrap();
+//! flash.download_file(&delta, 0).unwrap();
+//! assert_eq!(flash.read(0x08000500, 1).unwrap(), vec![0x42]);
+//! ```
+
+use ::elements::{Image, ImageElement};
+use ::file::DfuseFile;
+use ::flash::{render, FlashLayout};
+
+impl DfuseFile {
+    /// Build a delta patch from `old` to `new`
+    ///
+    /// Targets are matched by alternate and name. Changes are rounded to the
+    /// erase sectors of `layout`. A target with an element outside of
+    /// `layout`, like option bytes, is copied whole when it has changed.
+    ///
+    /// The suffix of `new` is used for the delta.
+    pub fn delta(old: &DfuseFile, new: &DfuseFile, layout: &FlashLayout) -> DfuseFile {
+        let mut delta = DfuseFile::new();
+        *delta.suffix_mut() = new.suffix().clone();
+
+        for image in new.images() {
+            let previous = old.images()
+                .iter()
+                .find(|i| i.alternate == image.alternate && i.name == image.name);
+
+            if let Some(patch) = delta_image(previous, image, layout) {
+                delta.images_mut().push(patch);
+            }
+        }
+
+        delta
+    }
+}
+
+fn in_layout(image: &Image, layout: &FlashLayout) -> bool {
+    image.elements.iter().all(|e| layout.covers(e.start_adress, e.end_adress()))
+}
+
+fn delta_image(old: Option<&Image>, new: &Image, layout: &FlashLayout) -> Option<Image> {
+    let empty = Image {
+        name: new.name.clone(),
+        alternate: new.alternate,
+        elements: Vec::new(),
+    };
+    let old = old.unwrap_or(&empty);
+
+    if !in_layout(old, layout) || !in_layout(new, layout) {
+        return if old.elements != new.elements {
+            Some(new.clone())
+        } else {
+            None
+        };
+    }
+
+    let mut elements: Vec<ImageElement> = Vec::new();
+    for sector in layout.sector_list() {
+        let touched = old.elements
+            .iter()
+            .chain(new.elements.iter())
+            .any(|e| (sector.start as u64) < e.end_adress() && (e.start_adress as u64) < sector.end());
+        if !touched {
+            continue;
+        }
+
+        let data = render(new, sector.start, sector.size);
+        if render(old, sector.start, sector.size) == data {
+            continue;
+        }
+
+        // Consecutive sectors are sent as a single element
+        match elements.last_mut() {
+            Some(ref mut last) if last.end_adress() == sector.start as u64 => {
+                last.data.extend_from_slice(&data);
+                continue;
+            }
+            _ => {}
+        }
+        elements.push(ImageElement::new(sector.start, data));
+    }
+
+    if elements.is_empty() {
+        None
+    } else {
+        Some(Image {
+            name: new.name.clone(),
+            alternate: new.alternate,
+            elements,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::file::DfuseFile;
+    use ::flash::{FlashLayout, FlashMemory};
+
+    fn layout() -> FlashLayout {
+        FlashLayout::new(0x08000000).sectors(4, 0x100).sectors(2, 0x400)
+    }
+
+    fn firmware(data: Vec<u8>) -> DfuseFile {
+        let mut file = DfuseFile::new();
+        file.add_image("ST...", 0, 0x08000000, data);
+        file.add_image("Option Bytes", 1, 0x1FFFF800, vec![0xAA, 0x55]);
+        file
+    }
+
+    fn flashed(files: &[&DfuseFile]) -> FlashMemory {
+        let mut flash = FlashMemory::new(layout());
+        for file in files {
+            flash.download_file(file, 0).unwrap();
+        }
+        flash
+    }
+
+    fn pattern(len: usize, seed: u8) -> Vec<u8> {
+        (0..len).map(|i| (i as u8).wrapping_mul(seed)).collect()
+    }
+
+    #[test]
+    fn same_files_give_empty_delta() {
+        let file = firmware(pattern(0x900, 3));
+        assert!(DfuseFile::delta(&file, &file, &layout()).images().is_empty());
+    }
+
+    #[test]
+    fn delta_only_hold_changed_sectors() {
+        let old = firmware(pattern(0x900, 3));
+        let mut data = pattern(0x900, 3);
+        data[0x150] ^= 0xFF;
+        data[0x250] ^= 0xFF;
+        data[0x850] ^= 0xFF;
+        let new = firmware(data);
+
+        let delta = DfuseFile::delta(&old, &new, &layout());
+        assert_eq!(delta.images().len(), 1);
+
+        let elements = &delta.images()[0].elements;
+        assert_eq!(elements.len(), 2);
+        assert_eq!(elements[0].start_adress, 0x08000100);
+        assert_eq!(elements[0].data.len(), 0x200);
+        assert_eq!(elements[1].start_adress, 0x08000800);
+        assert_eq!(elements[1].data.len(), 0x400);
+
+        assert_eq!(flashed(&[&old, &delta]), flashed(&[&new]));
+    }
+
+    #[test]
+    fn delta_erase_removed_data() {
+        let old = firmware(pattern(0x900, 5));
+        let new = firmware(pattern(0x300, 5));
+
+        let delta = DfuseFile::delta(&old, &new, &layout());
+        assert_eq!(flashed(&[&old, &delta]), flashed(&[&new]));
+    }
+
+    #[test]
+    fn delta_copy_changed_target_outside_layout() {
+        let old = firmware(pattern(0x100, 7));
+        let mut new = old.clone();
+        new.images_mut()[1].elements[0].data[0] = 0xBB;
+
+        let delta = DfuseFile::delta(&old, &new, &layout());
+        assert_eq!(delta.images(), &new.images()[1..]);
+    }
+}
@@ -0,0 +1,366 @@
+// Copyright © 2016 - Samuel Dolt <samuel@dolt.ch>
+//
+// See the COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Database of STM32 parts and their `DfuSe` bootloader
+//!
+//! The database is a text file, a section per part:
+//!
+//! ```text
+//! # Comment
+//! [STM32F405]
+//! usb = 0483:DF11
+//! version = 0x2200
+//! 0 = @Internal Flash  /0x08000000/04*016Kg,01*064Kg,07*128Kg
+//! 1 = @Option Bytes  /0x1FFFC000/01*016 e
+//! ```
+//!
+//! Memories are given by alternate setting, with the descriptor string the
+//! bootloader reports for it.
+//!
+//! # Examples
+//!
+//! ```
+//! use dfuse::device::Database;
+//!
+//! let db = Database::builtin();
+//!
+//! let mut file = db.create("STM32F405").unwrap();
+//! file.add_image("Internal Flash", 0, 0x08000000, vec![0x00; 0x100]);
+//! assert!(db.validate("STM32F405", &file).is_ok());
+//!
+//! file.add_image("Internal Flash", 0, 0x08100000, vec![0x00; 0x100]);
+//! assert!(db.validate("STM32F405", &file).is_err());
+//! ```
+
+use std::io::{self, ErrorKind, Read};
+
+use ::elements::Suffix;
+use ::error::{Error, Result};
+use ::file::DfuseFile;
+use ::flash::FlashLayout;
+
+const BUILTIN: &str = include_str!("devices.txt");
+
+/// A memory, as exposed on an alternate setting
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Memory {
+    pub alternate: u8,
+    pub name: String,
+    pub layout: FlashLayout,
+}
+
+impl Memory {
+    /// Parse a `DfuSe` memory descriptor, like
+    /// `@Internal Flash  /0x08000000/04*016Kg,01*064Kg,07*128Kg`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dfuse::device::Memory;
+    ///
+    /// let memory = Memory::from_descriptor(1, "@Option Bytes  /0x1FFFC000/01*016 e").unwrap();
+    /// assert_eq!(memory.name, "Option Bytes");
+    /// assert_eq!(memory.layout.size(), 16);
+    /// ```
+    pub fn from_descriptor(alternate: u8, descriptor: &str) -> Option<Memory> {
+        let descriptor = descriptor.trim();
+        if !descriptor.starts_with('@') {
+            return None;
+        }
+
+        let mut parts = descriptor[1..].split('/');
+        let name = parts.next()?.trim().to_string();
+
+        let mut layout: Option<FlashLayout> = None;
+        while let Some(adress) = parts.next() {
+            let adress = adress.trim();
+            if !adress.starts_with("0x") && !adress.starts_with("0X") {
+                return None;
+            }
+            let base = u32::from_str_radix(&adress[2..], 16).ok()?;
+
+            let mut current = match layout {
+                Some(layout) => layout.at(base),
+                None => FlashLayout::new(base),
+            };
+            for sectors in parts.next()?.split(',').filter(|s| !s.trim().is_empty()) {
+                let (count, size) = parse_sectors(sectors.trim())?;
+                current = current.try_sectors(count, size)?;
+            }
+            layout = Some(current);
+        }
+
+        Some(Memory {
+            alternate,
+            name,
+            layout: layout?,
+        })
+    }
+}
+
+/// Parse `NN*SSSut`, `u` being the unit (` `, `K` or `M`) and `t` the type
+fn parse_sectors(txt: &str) -> Option<(u32, u32)> {
+    let star = txt.find('*')?;
+    let count = txt[..star].parse().ok()?;
+
+    let rest = &txt[star + 1..];
+    let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+    let size: u32 = rest[..digits].parse().ok()?;
+    let size = match rest[digits..].chars().next() {
+        Some('K') => size.checked_mul(1024)?,
+        Some('M') => size.checked_mul(1024 * 1024)?,
+        _ => size,
+    };
+
+    Some((count, size))
+}
+
+/// A part and its bootloader
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Device {
+    pub part: String,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    /// Device release number, the default suffix firmware version
+    pub version: u16,
+    pub memories: Vec<Memory>,
+}
+
+impl Device {
+    pub fn memory(&self, alternate: u8) -> Option<&Memory> {
+        self.memories.iter().find(|m| m.alternate == alternate)
+    }
+
+    /// Suffix of a file for this device
+    pub fn suffix(&self) -> Suffix {
+        Suffix {
+            fw_version: self.version,
+            usb_pid: self.product_id,
+            usb_vid: self.vendor_id,
+            vendor_data: Vec::new(),
+        }
+    }
+
+    /// An empty file for this device
+    pub fn new_file(&self) -> DfuseFile {
+        let mut file = DfuseFile::new();
+        *file.suffix_mut() = self.suffix();
+        file
+    }
+
+    /// Check that `file` can be downloaded to this device
+    ///
+    /// The suffix must match the USB IDs, `0xFFFF` matching any value, and
+    /// every element must be in the memory of its target.
+    pub fn validate(&self, file: &DfuseFile) -> Result<()> {
+        let suffix = file.suffix();
+        if (suffix.usb_vid != 0xFFFF && suffix.usb_vid != self.vendor_id) ||
+           (suffix.usb_pid != 0xFFFF && suffix.usb_pid != self.product_id) {
+            return Err(Error::WrongDevice {
+                vendor_id: suffix.usb_vid,
+                product_id: suffix.usb_pid,
+            });
+        }
+
+        for image in file.images() {
+            let memory = match self.memory(image.alternate) {
+                Some(memory) => memory,
+                None => return Err(Error::UnknownAlternate(image.alternate)),
+            };
+
+            for element in image.elements.iter().filter(|e| !e.data.is_empty()) {
+                if !memory.layout.covers(element.start_adress, element.end_adress()) {
+                    return Err(Error::Unmapped(element.start_adress));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A list of devices, looked up by part number
+#[derive(Debug, Clone, Default)]
+pub struct Database {
+    devices: Vec<Device>,
+}
+
+impl Database {
+    /// An empty database
+    pub fn new() -> Database {
+        Database { devices: Vec::new() }
+    }
+
+    /// The database shipped with this crate
+    pub fn builtin() -> Database {
+        let mut db = Database::new();
+        db.parse(BUILTIN).expect("invalid builtin device database");
+        db
+    }
+
+    pub fn devices(&self) -> &[Device] {
+        &self.devices
+    }
+
+    /// Add `device`, replacing a device with the same part number
+    pub fn add(&mut self, device: Device) {
+        self.devices.retain(|d| !d.part.eq_ignore_ascii_case(&device.part));
+        self.devices.push(device);
+    }
+
+    /// Add the devices of a database file
+    pub fn load<T: Read>(&mut self, buf: &mut T) -> io::Result<()> {
+        let mut txt = String::new();
+        buf.read_to_string(&mut txt)?;
+        self.parse(&txt)
+    }
+
+    /// Add the devices described by `txt`, nothing is added on error
+    pub fn parse(&mut self, txt: &str) -> io::Result<()> {
+        let mut devices: Vec<Device> = Vec::new();
+
+        for (n, line) in txt.lines().enumerate() {
+            let invalid = |msg: &str| io::Error::new(ErrorKind::InvalidData, format!("line {}: {}", n + 1, msg));
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if line.starts_with('[') && line.ends_with(']') {
+                devices.push(Device {
+                    part: line[1..line.len() - 1].trim().to_string(),
+                    vendor_id: 0x0483,
+                    product_id: 0xDF11,
+                    version: 0xFFFF,
+                    memories: Vec::new(),
+                });
+                continue;
+            }
+
+            let device = devices.last_mut().ok_or_else(|| invalid("no part"))?;
+            let eq = line.find('=').ok_or_else(|| invalid("expected key = value"))?;
+            let (key, value) = (line[..eq].trim(), line[eq + 1..].trim());
+
+            match key {
+                "usb" => {
+                    let ids: Vec<&str> = value.split(':').collect();
+                    if ids.len() != 2 {
+                        return Err(invalid("expected vid:pid"));
+                    }
+                    device.vendor_id = u16::from_str_radix(ids[0], 16).map_err(|_| invalid("invalid vendor id"))?;
+                    device.product_id = u16::from_str_radix(ids[1], 16).map_err(|_| invalid("invalid product id"))?;
+                }
+                "version" => {
+                    let version = value.trim_start_matches("0x").trim_start_matches("0X");
+                    device.version = u16::from_str_radix(version, 16).map_err(|_| invalid("invalid version"))?;
+                }
+                _ => {
+                    let alternate = key.parse().map_err(|_| invalid("unknown key"))?;
+                    let memory = Memory::from_descriptor(alternate, value)
+                        .ok_or_else(|| invalid("invalid memory descriptor"))?;
+                    device.memories.push(memory);
+                }
+            }
+        }
+
+        for device in devices {
+            self.add(device);
+        }
+        Ok(())
+    }
+
+    /// Device with part number `part`, ignoring case
+    pub fn get(&self, part: &str) -> Option<&Device> {
+        self.devices.iter().find(|d| d.part.eq_ignore_ascii_case(part))
+    }
+
+    fn device(&self, part: &str) -> Result<&Device> {
+        self.get(part).ok_or_else(|| Error::UnknownPart(part.to_string()))
+    }
+
+    /// An empty file for `part`
+    pub fn create(&self, part: &str) -> Result<DfuseFile> {
+        Ok(self.device(part)?.new_file())
+    }
+
+    /// Check that `file` can be downloaded to `part`
+    pub fn validate(&self, part: &str, file: &DfuseFile) -> Result<()> {
+        self.device(part)?.validate(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_descriptor() {
+        let memory = Memory::from_descriptor(0, "@Internal Flash  /0x08000000/04*016Kg,01*064Kg,07*128Kg")
+            .unwrap();
+        assert_eq!(memory.name, "Internal Flash");
+        assert_eq!(memory.layout,
+                   FlashLayout::new(0x08000000).sectors(4, 0x4000).sectors(1, 0x10000).sectors(7, 0x20000));
+
+        let memory = Memory::from_descriptor(1, "@Option Bytes  /0x1FFF7800/01*040 e,/0x1FFFF800/01*040 e")
+            .unwrap();
+        assert_eq!(memory.layout.sector_list()[1].start, 0x1FFFF800);
+        assert!(!memory.layout.covers(0x1FFF7800, 0x1FFF7830));
+
+        assert_eq!(Memory::from_descriptor(0, "Internal Flash /0x08000000/04*016Kg"), None);
+        assert_eq!(Memory::from_descriptor(0, "@Internal Flash /0x08000000/04016Kg"), None);
+        assert_eq!(Memory::from_descriptor(0, "@Internal Flash"), None);
+        assert_eq!(Memory::from_descriptor(0, "@Internal Flash /0xFFFFF000/02*004Kg"), None);
+    }
+
+    #[test]
+    fn builtin_database() {
+        let db = Database::builtin();
+        let device = db.get("stm32f429").unwrap();
+        assert_eq!(device.memory(0).unwrap().layout.size(), 2 * 1024 * 1024);
+        assert_eq!(device.suffix().usb_pid, 0xDF11);
+        assert!(db.devices().iter().all(|d| d.memory(0).is_some()));
+    }
+
+    #[test]
+    fn load_database() {
+        let mut db = Database::builtin();
+        let txt = "[STM32F405]\nusb = 1234:5678\n0 = @Flash /0x08000000/2*1Kg\n";
+        db.load(&mut txt.as_bytes()).unwrap();
+        assert_eq!(db.get("STM32F405").unwrap().vendor_id, 0x1234);
+        assert_eq!(db.get("STM32F405").unwrap().memories.len(), 1);
+
+        assert!(db.parse("usb = 0483:DF11\n").is_err());
+        assert!(db.parse("[X]\n0 = Flash\n").is_err());
+        assert!(db.get("X").is_none());
+    }
+
+    #[test]
+    fn validate_file() {
+        let db = Database::builtin();
+        assert!(db.create("STM32F999").is_err());
+
+        let mut file = db.create("STM32F042").unwrap();
+        file.add_image("Option Bytes", 1, 0x1FFFF800, vec![0xAA; 16]);
+        assert!(db.validate("STM32F042", &file).is_ok());
+
+        file.add_image("OTP", 2, 0x1FFF7800, vec![0x00]);
+        match db.validate("STM32F042", &file) {
+            Err(Error::UnknownAlternate(2)) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+
+        let mut file = DfuseFile::new();
+        file.set_vendor_id(0x1234);
+        match db.validate("STM32F042", &file) {
+            Err(Error::WrongDevice { vendor_id: 0x1234, .. }) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,438 @@
+// Copyright © 2016 - Samuel Dolt <samuel@dolt.ch>
+//
+// See the COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Structural comparison of two `DfuseFile`
+//!
+//! Targets are matched by alternate and name, elements by start address.
+//!
+//! # Examples
+//!
+//! ```
+//! use dfuse::DfuseFile;
+//!
+//! let mut old = DfuseFile::new();
+//! old.add_image("ST...", 0, 0x08000000, vec![0x00, 0x01, 0x02, 0x03]);
+//!
+//! let mut new = old.clone();
+//! new.set_version(0x0220);
+//!
+//! let diff = old.diff(&new);
+//! assert_eq!(diff.suffix.len(), 1);
+//! assert!(diff.targets.is_empty());
+//! ```
+
+use std::fmt;
+
+use ::elements::{Image, ImageElement, Suffix};
+use ::file::DfuseFile;
+
+/// A range of memory, `size` bytes starting at `start`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddressRange {
+    pub start: u32,
+    pub size: u32,
+}
+
+impl fmt::Display for AddressRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "0x{:08X}..0x{:08X} ({} bytes)",
+               self.start,
+               self.start as u64 + self.size as u64,
+               self.size)
+    }
+}
+
+/// A field of the DFU suffix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SuffixField {
+    FirmwareVersion,
+    ProductId,
+    VendorId,
+}
+
+impl fmt::Display for SuffixField {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            SuffixField::FirmwareVersion => "firmware version",
+            SuffixField::ProductId => "product id",
+            SuffixField::VendorId => "vendor id",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A suffix field whose value differ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SuffixChange {
+    pub field: SuffixField,
+    pub old: u16,
+    pub new: u16,
+}
+
+/// Difference between two elements of a target
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ElementDiff {
+    /// Element only present in the new file
+    Added { start_adress: u32, size: u32 },
+    /// Element only present in the old file
+    Removed { start_adress: u32, size: u32 },
+    /// Element present in both files with a different content
+    ///
+    /// A change in size is reported as a changed range after the shortest data
+    Changed {
+        start_adress: u32,
+        old_size: u32,
+        new_size: u32,
+        ranges: Vec<AddressRange>,
+    },
+}
+
+impl ElementDiff {
+    /// Number of bytes affected by this difference
+    pub fn changed_bytes(&self) -> u64 {
+        match *self {
+            ElementDiff::Added { size, .. } |
+            ElementDiff::Removed { size, .. } => size as u64,
+            ElementDiff::Changed { ref ranges, .. } => {
+                ranges.iter().fold(0, |sum, r| sum + r.size as u64)
+            }
+        }
+    }
+}
+
+/// Difference between two targets
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TargetDiff {
+    /// Target only present in the new file
+    Added { alternate: u8, name: Option<String> },
+    /// Target only present in the old file
+    Removed { alternate: u8, name: Option<String> },
+    /// Target present in both files with different elements
+    Changed {
+        alternate: u8,
+        name: Option<String>,
+        elements: Vec<ElementDiff>,
+    },
+}
+
+/// Result of the comparison of two `DfuseFile`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileDiff {
+    pub suffix: Vec<SuffixChange>,
+    pub targets: Vec<TargetDiff>,
+}
+
+impl FileDiff {
+    /// `true` if both files have the same content
+    pub fn is_empty(&self) -> bool {
+        self.suffix.is_empty() && self.targets.is_empty()
+    }
+}
+
+impl DfuseFile {
+    /// Compare this file, seen as the old one, with `other`
+    pub fn diff(&self, other: &DfuseFile) -> FileDiff {
+        diff(self, other)
+    }
+}
+
+/// Compare two `DfuseFile`
+pub fn diff(old: &DfuseFile, new: &DfuseFile) -> FileDiff {
+    FileDiff {
+        suffix: diff_suffix(old.suffix(), new.suffix()),
+        targets: diff_targets(old.images(), new.images()),
+    }
+}
+
+fn diff_suffix(old: &Suffix, new: &Suffix) -> Vec<SuffixChange> {
+    let fields = [(SuffixField::FirmwareVersion, old.fw_version, new.fw_version),
+                  (SuffixField::ProductId, old.usb_pid, new.usb_pid),
+                  (SuffixField::VendorId, old.usb_vid, new.usb_vid)];
+
+    fields.iter()
+        .filter(|&&(_, old, new)| old != new)
+        .map(|&(field, old, new)| {
+            SuffixChange {
+                field,
+                old,
+                new,
+            }
+        })
+        .collect()
+}
+
+fn diff_targets(old: &[Image], new: &[Image]) -> Vec<TargetDiff> {
+    let mut matched = vec![false; new.len()];
+    let mut targets = Vec::new();
+
+    for o in old {
+        let found = new.iter()
+            .enumerate()
+            .position(|(i, n)| !matched[i] && n.alternate == o.alternate && n.name == o.name);
+
+        match found {
+            Some(i) => {
+                matched[i] = true;
+                let elements = diff_elements(&o.elements, &new[i].elements);
+                if !elements.is_empty() {
+                    targets.push(TargetDiff::Changed {
+                        alternate: o.alternate,
+                        name: o.name.clone(),
+                        elements,
+                    });
+                }
+            }
+            None => {
+                targets.push(TargetDiff::Removed {
+                    alternate: o.alternate,
+                    name: o.name.clone(),
+                })
+            }
+        }
+    }
+
+    for (n, _) in new.iter().zip(matched).filter(|&(_, m)| !m) {
+        targets.push(TargetDiff::Added {
+            alternate: n.alternate,
+            name: n.name.clone(),
+        });
+    }
+
+    targets
+}
+
+fn diff_elements(old: &[ImageElement], new: &[ImageElement]) -> Vec<ElementDiff> {
+    let mut matched = vec![false; new.len()];
+    let mut elements = Vec::new();
+
+    for o in old {
+        let found = new.iter()
+            .enumerate()
+            .position(|(i, n)| !matched[i] && n.start_adress == o.start_adress);
+
+        match found {
+            Some(i) => {
+                matched[i] = true;
+                let ranges = changed_ranges(o.start_adress, &o.data, &new[i].data);
+                if !ranges.is_empty() {
+                    elements.push(ElementDiff::Changed {
+                        start_adress: o.start_adress,
+                        old_size: o.data.len() as u32,
+                        new_size: new[i].data.len() as u32,
+                        ranges,
+                    });
+                }
+            }
+            None => {
+                elements.push(ElementDiff::Removed {
+                    start_adress: o.start_adress,
+                    size: o.data.len() as u32,
+                })
+            }
+        }
+    }
+
+    for (n, _) in new.iter().zip(matched).filter(|&(_, m)| !m) {
+        elements.push(ElementDiff::Added {
+            start_adress: n.start_adress,
+            size: n.data.len() as u32,
+        });
+    }
+
+    elements.sort_by_key(|e| match *e {
+        ElementDiff::Added { start_adress, .. } |
+        ElementDiff::Removed { start_adress, .. } |
+        ElementDiff::Changed { start_adress, .. } => start_adress,
+    });
+    elements
+}
+
+/// Ranges of differing bytes between `old` and `new`, both starting at `start`
+fn changed_ranges(start: u32, old: &[u8], new: &[u8]) -> Vec<AddressRange> {
+    let len = old.len().max(new.len());
+    let mut ranges: Vec<AddressRange> = Vec::new();
+    let mut current: Option<usize> = None;
+
+    for i in 0..(len + 1) {
+        let differ = i < len && old.get(i) != new.get(i);
+
+        match (current, differ) {
+            (None, true) => current = Some(i),
+            (Some(begin), false) => {
+                ranges.push(AddressRange {
+                    start: start.wrapping_add(begin as u32),
+                    size: (i - begin) as u32,
+                });
+                current = None;
+            }
+            _ => {}
+        }
+    }
+
+    ranges
+}
+
+fn fmt_target(f: &mut fmt::Formatter, alternate: u8, name: &Option<String>) -> fmt::Result {
+    write!(f, "target {}", alternate)?;
+    if let Some(ref name) = *name {
+        write!(f, " \"{}\"", name)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for FileDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for change in &self.suffix {
+            writeln!(f,
+                     "suffix {}: 0x{:04X} -> 0x{:04X}",
+                     change.field,
+                     change.old,
+                     change.new)?;
+        }
+
+        for target in &self.targets {
+            match *target {
+                TargetDiff::Added { alternate, ref name } => {
+                    fmt_target(f, alternate, name)?;
+                    writeln!(f, ": added")?;
+                }
+                TargetDiff::Removed { alternate, ref name } => {
+                    fmt_target(f, alternate, name)?;
+                    writeln!(f, ": removed")?;
+                }
+                TargetDiff::Changed { alternate, ref name, ref elements } => {
+                    fmt_target(f, alternate, name)?;
+                    writeln!(f, ": changed")?;
+                    for element in elements {
+                        fmt_element(f, element)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn fmt_element(f: &mut fmt::Formatter, element: &ElementDiff) -> fmt::Result {
+    match *element {
+        ElementDiff::Added { start_adress, size } => {
+            writeln!(f, "  element 0x{:08X}: added ({} bytes)", start_adress, size)
+        }
+        ElementDiff::Removed { start_adress, size } => {
+            writeln!(f, "  element 0x{:08X}: removed ({} bytes)", start_adress, size)
+        }
+        ElementDiff::Changed { start_adress, old_size, new_size, ref ranges } => {
+            write!(f,
+                   "  element 0x{:08X}: {} bytes changed",
+                   start_adress,
+                   element.changed_bytes())?;
+            if old_size != new_size {
+                write!(f, ", size {} -> {}", old_size, new_size)?;
+            }
+            writeln!(f)?;
+            for range in ranges {
+                writeln!(f, "    {}", range)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::file::DfuseFile;
+
+    fn file(data: Vec<u8>) -> DfuseFile {
+        let mut file = DfuseFile::new();
+        file.add_image("ST...", 0, 0x08000000, data);
+        file
+    }
+
+    #[test]
+    fn identical_files_have_empty_diff() {
+        let a = file(vec![0x01, 0x02, 0x03]);
+        assert!(a.diff(&a.clone()).is_empty());
+    }
+
+    #[test]
+    fn diff_report_changed_ranges() {
+        let old = file(vec![0x00, 0x01, 0x02, 0x03, 0x04, 0x05]);
+        let new = file(vec![0xFF, 0x01, 0x02, 0xFF, 0xFF, 0x05, 0x06]);
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.targets,
+                   vec![TargetDiff::Changed {
+                            alternate: 0,
+                            name: Some("ST...".to_string()),
+                            elements: vec![ElementDiff::Changed {
+                                               start_adress: 0x08000000,
+                                               old_size: 6,
+                                               new_size: 7,
+                                               ranges: vec![AddressRange {
+                                                                start: 0x08000000,
+                                                                size: 1,
+                                                            },
+                                                            AddressRange {
+                                                                start: 0x08000003,
+                                                                size: 2,
+                                                            },
+                                                            AddressRange {
+                                                                start: 0x08000006,
+                                                                size: 1,
+                                                            }],
+                                           }],
+                        }]);
+        assert_eq!(match diff.targets[0] {
+                       TargetDiff::Changed { ref elements, .. } => elements[0].changed_bytes(),
+                       _ => 0,
+                   },
+                   4);
+    }
+
+    #[test]
+    fn diff_match_targets_by_alternate_and_name() {
+        let old = file(vec![0x00]);
+        let mut new = DfuseFile::new();
+        new.add_image("Other", 0, 0x08000000, vec![0x00]);
+        new.add_unamed_image(1, 0x1FFFF800, vec![0xAA, 0x55]);
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.targets,
+                   vec![TargetDiff::Removed {
+                            alternate: 0,
+                            name: Some("ST...".to_string()),
+                        },
+                        TargetDiff::Added {
+                            alternate: 0,
+                            name: Some("Other".to_string()),
+                        },
+                        TargetDiff::Added {
+                            alternate: 1,
+                            name: None,
+                        }]);
+    }
+
+    #[test]
+    fn diff_report_suffix_fields() {
+        let old = file(vec![]);
+        let mut new = file(vec![]);
+        new.set_vendor_id(0x0483);
+
+        assert_eq!(old.diff(&new).suffix,
+                   vec![SuffixChange {
+                            field: SuffixField::VendorId,
+                            old: 0xFFFF,
+                            new: 0x0483,
+                        }]);
+    }
+}
@@ -0,0 +1,301 @@
+// Copyright © 2016 - Samuel Dolt <samuel@dolt.ch>
+//
+// See the COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! SHA-256 and SHA-1 digests of a file, its targets and their elements
+//!
+//! The digest of a target is the one of its flattened content, from its
+//! first to its last address, bytes not covered by an element being `0xFF`.
+//! It matches the digest of the same range read back from the device.
+//!
+//! # Examples
+//!
+//! ```
+//! use dfuse::DfuseFile;
+//!
+//! let mut file = DfuseFile::new();
+//! file.add_image("Internal Flash", 0, 0x08000000, b"abc".to_vec());
+//!
+//! let digests = file.digests().unwrap();
+//! assert_eq!(digests.images[0].elements[0].hashes.sha256_hex(),
+//!            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+//! assert_eq!(digests.images[0].flat, digests.images[0].elements[0].hashes);
+//! ```
+
+use std::fmt::Write as FmtWrite;
+use std::io::{self, Write};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+use ::elements::Image;
+use ::error::{Error, Result};
+use ::file::DfuseFile;
+use ::flash::ERASED;
+use ::tools::{CRCParams, WriterWithCRC};
+
+/// Data are hashed by chunks, so every hasher sees them while in cache
+const CHUNK_SIZE: usize = 4096;
+
+/// SHA-256 and SHA-1 of some data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hashes {
+    pub sha256: [u8; 32],
+    pub sha1: [u8; 20],
+}
+
+/// Lowercase hexadecimal
+pub(crate) fn hex(data: &[u8]) -> String {
+    let mut out = String::with_capacity(2 * data.len());
+    for b in data {
+        let _ = write!(out, "{:02x}", b);
+    }
+    out
+}
+
+impl Hashes {
+    /// SHA-256 as lowercase hexadecimal
+    pub fn sha256_hex(&self) -> String {
+        hex(&self.sha256)
+    }
+
+    /// SHA-1 as lowercase hexadecimal
+    pub fn sha1_hex(&self) -> String {
+        hex(&self.sha1)
+    }
+}
+
+/// Digests of an element data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElementDigests {
+    pub start_adress: u32,
+    pub size: usize,
+    pub hashes: Hashes,
+}
+
+/// Digests of a target
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageDigests {
+    pub alternate: u8,
+    pub name: Option<String>,
+    /// Flattened range, `None` if the target holds no data
+    pub bounds: Option<(u32, u64)>,
+    /// Digests of the flattened range
+    pub flat: Hashes,
+    pub elements: Vec<ElementDigests>,
+}
+
+/// Digests of a file, as written by `write_to`, and of its content
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileDigests {
+    pub size: usize,
+    pub file: Hashes,
+    pub images: Vec<ImageDigests>,
+}
+
+/// Compute both digests of everything written
+struct Hasher {
+    sha256: Sha256,
+    sha1: Sha1,
+}
+
+impl Hasher {
+    fn new() -> Hasher {
+        Hasher {
+            sha256: Sha256::new(),
+            sha1: Sha1::new(),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.sha256.update(data);
+        self.sha1.update(data);
+    }
+
+    fn fill(&mut self, len: u64) {
+        let chunk = [ERASED; 256];
+        let mut left = len;
+        while left > 0 {
+            let n = left.min(chunk.len() as u64) as usize;
+            self.update(&chunk[..n]);
+            left -= n as u64;
+        }
+    }
+
+    fn finalize(self) -> Hashes {
+        let mut hashes = Hashes {
+            sha256: [0; 32],
+            sha1: [0; 20],
+        };
+        hashes.sha256.copy_from_slice(&self.sha256.finalize());
+        hashes.sha1.copy_from_slice(&self.sha1.finalize());
+        hashes
+    }
+}
+
+impl Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// `true` if the elements are in address order, without overlap, so the
+/// flattened target can be hashed while writing the file
+fn is_sorted(image: &Image) -> bool {
+    let mut end = 0;
+    for element in image.elements.iter().filter(|e| !e.data.is_empty()) {
+        if (element.start_adress as u64) < end {
+            return false;
+        }
+        end = element.end_adress();
+    }
+    true
+}
+
+impl DfuseFile {
+    /// Digests of the file, of every target and of every element
+    ///
+    /// Everything is computed while serializing the file once, but for
+    /// targets whose elements are not in address order. Fail if elements of a
+    /// target overlap.
+    pub fn digests(&self) -> Result<FileDigests> {
+        let mut file = WriterWithCRC::new(Hasher::new(), CRCParams::CRC_32_JAMCRC);
+        let mut images = Vec::with_capacity(self.images().len());
+
+        self.prefix()
+            .ok_or(Error::InvalidFile("more than 255 targets"))?
+            .write_to(&mut file)?;
+
+        for image in self.images() {
+            if let Some(adress) = image.find_overlap() {
+                return Err(Error::Overlap {
+                    alternate: image.alternate,
+                    adress,
+                });
+            }
+
+            image.target_prefix().write_to(&mut file)?;
+
+            let bounds = image.bounds();
+            let sorted = is_sorted(image);
+            let mut flat = Hasher::new();
+            let mut adress = bounds.map_or(0, |(start, _)| start as u64);
+            let mut elements = Vec::with_capacity(image.elements.len());
+
+            for element in &image.elements {
+                file.write_all(&element.header())?;
+
+                if sorted && !element.data.is_empty() {
+                    flat.fill(element.start_adress as u64 - adress);
+                    adress = element.end_adress();
+                }
+
+                let mut hasher = Hasher::new();
+                for chunk in element.data.chunks(CHUNK_SIZE) {
+                    file.write_all(chunk)?;
+                    hasher.update(chunk);
+                    if sorted {
+                        flat.update(chunk);
+                    }
+                }
+
+                elements.push(ElementDigests {
+                    start_adress: element.start_adress,
+                    size: element.data.len(),
+                    hashes: hasher.finalize(),
+                });
+            }
+
+            if !sorted {
+                let (start, end) = bounds.unwrap_or((0, 0));
+                image.write_flat_to(&mut flat, start, end, ERASED)?;
+            }
+
+            images.push(ImageDigests {
+                alternate: image.alternate,
+                name: image.name.clone(),
+                bounds,
+                flat: flat.finalize(),
+                elements,
+            });
+        }
+
+        self.suffix().write_to(&mut file)?;
+        let crc = file.crc();
+        let mut hasher = file.into_inner();
+        hasher.write_u32::<LittleEndian>(crc)?;
+
+        Ok(FileDigests {
+            size: self.size(),
+            file: hasher.finalize(),
+            images,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::elements::ImageElement;
+
+    fn digest(data: &[u8]) -> Hashes {
+        let mut hasher = Hasher::new();
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    #[test]
+    fn known_digests() {
+        let hashes = digest(b"abc");
+        assert_eq!(hashes.sha256_hex(),
+                   "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(hashes.sha1_hex(), "a9993e364706816aba3e25717850c26c9cd0d89d");
+    }
+
+    #[test]
+    fn file_digests() {
+        let mut file = DfuseFile::new();
+        file.add_image("Internal Flash", 0, 0x08000000, vec![0x01; 0x10]);
+        file.images_mut()[0].elements.push(ImageElement::new(0x08000020, vec![0x02; 0x3000]));
+        file.add_image("Option Bytes", 1, 0x1FFFF800, vec![0xAA, 0x55]);
+        file.images_mut()[1].elements.insert(0, ImageElement::new(0x1FFFF804, vec![0x00]));
+        file.suffix_mut().vendor_data = vec![0x42];
+
+        let digests = file.digests().unwrap();
+
+        let mut buf = vec![];
+        file.write_to(&mut buf).unwrap();
+        assert_eq!(digests.size, buf.len());
+        assert_eq!(digests.file, digest(&buf));
+
+        let flash = &digests.images[0];
+        assert_eq!(flash.bounds, Some((0x08000000, 0x08003020)));
+        assert_eq!(flash.elements[1].hashes, digest(&[0x02; 0x3000]));
+        assert_eq!(flash.flat,
+                   digest(&file.images()[0].flatten(0x08000000, 0x08003020, 0xFF).unwrap()));
+
+        let ob = &digests.images[1];
+        assert_eq!(ob.elements[0].start_adress, 0x1FFFF804);
+        assert_eq!(ob.flat, digest(&[0xAA, 0x55, 0xFF, 0xFF, 0x00]));
+    }
+
+    #[test]
+    fn reject_overlap() {
+        let mut file = DfuseFile::new();
+        file.add_image("Internal Flash", 0, 0x08000000, vec![0x01; 0x10]);
+        file.images_mut()[0].elements.push(ImageElement::new(0x08000008, vec![0x02]));
+        assert!(file.digests().is_err());
+    }
+}
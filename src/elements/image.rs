@@ -8,12 +8,21 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::io::Result;
-use byteorder::WriteBytesExt;
+#[cfg(feature = "std")]
+use std::io::{Error, ErrorKind, Result};
+#[cfg(feature = "std")]
+use byteorder::{ReadBytesExt, WriteBytesExt};
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use ::codec::{self, CodecError, ParseLimits, SliceReader};
 
 use super::ImageElement;
 use super::TargetPrefix;
 
+/// A target of a `DfuSe` file, one per alternate setting
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Image {
     pub name: Option<String>,
     pub alternate: u8,
@@ -21,24 +30,233 @@ pub struct Image {
 }
 
 impl Image {
+    /// Size in bytes of this image in a `DfuSe` file, target prefix included
     pub fn size(&self) -> usize {
-        274 + self.elements_size()
+        TargetPrefix::size() + self.elements_size()
     }
 
     fn elements_size(&self) -> usize {
         self.elements.iter().fold(0, |sum, x| sum + x.size())
     }
 
+    /// First address written by more than one element, if any
+    pub fn find_overlap(&self) -> Option<u32> {
+        let mut ranges: Vec<(u64, u64)> = self.elements
+            .iter()
+            .filter(|e| !e.data.is_empty())
+            .map(|e| (e.start_adress as u64, e.end_adress()))
+            .collect();
+        ranges.sort();
+
+        ranges.windows(2)
+            .find(|w| w[1].0 < w[0].1)
+            .map(|w| w[1].0 as u32)
+    }
+
+    /// Write `bytes` at `adress`
+    ///
+    /// Elements covering the range are patched, the rest extend the element
+    /// ending just before it, or go in new elements. `adress` plus the length
+    /// of `bytes` must fit in the 32 bit address space.
+    #[cfg(feature = "std")]
+    pub(crate) fn patch(&mut self, adress: u32, bytes: &[u8]) {
+        let end = adress as u64 + bytes.len() as u64;
+        let mut covered = vec![false; bytes.len()];
+
+        for element in &mut self.elements {
+            let from = (element.start_adress as u64).max(adress as u64);
+            let to = element.end_adress().min(end);
+            if from >= to {
+                continue;
+            }
+
+            let src = (from - adress as u64) as usize..(to - adress as u64) as usize;
+            let dst = (from - element.start_adress as u64) as usize..(to - element.start_adress as u64) as usize;
+            element.data[dst].copy_from_slice(&bytes[src.clone()]);
+            for c in &mut covered[src] {
+                *c = true;
+            }
+        }
+
+        let mut i = 0;
+        while i < bytes.len() {
+            if covered[i] {
+                i += 1;
+                continue;
+            }
+
+            let j = covered[i..].iter().position(|&c| c).map_or(bytes.len(), |n| i + n);
+            let start = adress + i as u32;
+            match self.elements.iter_mut().find(|e| !e.data.is_empty() && e.end_adress() == start as u64) {
+                Some(element) => element.data.extend_from_slice(&bytes[i..j]),
+                None => {
+                    let pos = self.elements
+                        .iter()
+                        .position(|e| e.start_adress > start)
+                        .unwrap_or(self.elements.len());
+                    self.elements.insert(pos, ImageElement::new(start, bytes[i..j].to_vec()));
+                }
+            }
+            i = j;
+        }
+    }
+
+    /// Same as `patch`, then join the elements meeting from `adress` to the
+    /// end of `bytes`, as when the bytes fill the gap between two elements
+    #[cfg(feature = "std")]
+    pub(crate) fn patch_and_join(&mut self, adress: u32, bytes: &[u8]) {
+        self.patch(adress, bytes);
+
+        let end = adress as u64 + bytes.len() as u64;
+        loop {
+            let meeting = self.elements.iter().enumerate().find_map(|(n, before)| {
+                let at = before.end_adress();
+                if before.data.is_empty() || at < adress as u64 || at > end {
+                    return None;
+                }
+                self.elements
+                    .iter()
+                    .position(|e| !e.data.is_empty() && e.start_adress as u64 == at)
+                    .map(|m| (n, m))
+            });
+
+            match meeting {
+                Some((n, m)) => {
+                    let after = self.elements.remove(m);
+                    let n = if m < n { n - 1 } else { n };
+                    self.elements[n].data.extend_from_slice(&after.data);
+                }
+                None => break,
+            }
+        }
+    }
+
+    pub(crate) fn target_prefix(&self) -> TargetPrefix {
+        TargetPrefix::new(self.name.clone(),
+                          self.alternate,
+                          self.elements_size() as u32,
+                          self.elements.len() as u32)
+    }
+
+    /// Build an image from its target prefix and elements
+    fn from_parts(target: TargetPrefix, elements: Vec<ImageElement>) -> Option<Image> {
+        let image = Image {
+            name: target.name().cloned(),
+            alternate: target.alternate(),
+            elements,
+        };
+
+        if image.elements_size() != target.image_size() as usize {
+            return None;
+        }
+
+        Some(image)
+    }
+
+    /// Append the encoded image, target prefix included, to `out`
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        self.target_prefix().encode(out);
+
+        for element in &self.elements {
+            element.encode(out);
+        }
+    }
+
+    pub fn decode(reader: &mut SliceReader) -> codec::Result<Image> {
+        Image::decode_limited(reader, &ParseLimits::default())
+    }
+
+    pub(crate) fn decode_limited(reader: &mut SliceReader, limits: &ParseLimits) -> codec::Result<Image> {
+        let target = TargetPrefix::decode(reader)?;
+        limits.check_elements(target.nb_elements() as usize)?;
+
+        // Every element has an 8 bytes header
+        if reader.remaining().len() / 8 < target.nb_elements() as usize {
+            return Err(CodecError::UnexpectedEnd);
+        }
+
+        let mut elements = Vec::new();
+        for _ in 0..target.nb_elements() {
+            elements.push(ImageElement::decode_limited(reader, limits)?);
+        }
+
+        Image::from_parts(target, elements).ok_or(CodecError::Invalid("target size mismatch"))
+    }
+
+    #[cfg(feature = "std")]
     pub fn write_to<T: WriteBytesExt>(&self, buf: &mut T) -> Result<()> {
-        let target = TargetPrefix::new(self.name.clone(),
-                                       self.alternate,
-                                       self.elements_size() as u32,
-                                       self.elements.len() as u32);
-        try!(target.write_to(buf));
+        self.target_prefix().write_to(buf)?;
 
         for element in &self.elements {
-            try!(element.write_to(buf));
+            element.write_to(buf)?;
         }
         Ok(())
     }
-}
\ No newline at end of file
+
+    #[cfg(feature = "std")]
+    pub fn read_from<T: ReadBytesExt>(buf: &mut T) -> Result<Image> {
+        Image::read_limited(buf, &ParseLimits::default())
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn read_limited<T: ReadBytesExt>(buf: &mut T, limits: &ParseLimits) -> Result<Image> {
+        let target = TargetPrefix::read_from(buf)?;
+        limits.check_elements(target.nb_elements() as usize)?;
+
+        let mut elements = Vec::new();
+        for _ in 0..target.nb_elements() {
+            elements.push(ImageElement::read_limited(buf, limits)?);
+        }
+
+        Image::from_parts(target, elements)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "target size mismatch"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(elements: Vec<(u32, usize)>) -> Image {
+        Image {
+            name: None,
+            alternate: 0,
+            elements: elements.into_iter()
+                .map(|(start, len)| ImageElement::new(start, vec![0; len]))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_image_find_overlap() {
+        assert_eq!(image(vec![(0x100, 0x10), (0x110, 0x10)]).find_overlap(), None);
+        assert_eq!(image(vec![(0x110, 0x10), (0x100, 0x11)]).find_overlap(), Some(0x110));
+        assert_eq!(image(vec![(0x100, 0x10), (0x108, 0)]).find_overlap(), None);
+    }
+
+    #[test]
+    fn test_image_patch() {
+        let mut image = image(vec![(0x100, 0x4), (0x108, 0x4), (0x120, 0x2)]);
+        image.patch(0x102, &[1, 2, 3, 4, 5, 6, 7, 8]);
+        image.patch(0x11C, &[9, 9]);
+
+        assert_eq!(image.elements,
+                   vec![ImageElement::new(0x100, vec![0, 0, 1, 2, 3, 4, 5, 6]),
+                        ImageElement::new(0x108, vec![7, 8, 0, 0]),
+                        ImageElement::new(0x11C, vec![9, 9]),
+                        ImageElement::new(0x120, vec![0, 0])]);
+    }
+
+    #[test]
+    fn test_image_patch_and_join() {
+        let mut image = image(vec![(0x100, 0x4), (0x108, 0x4), (0x120, 0x2)]);
+        image.patch_and_join(0x102, &[1, 2, 3, 4, 5, 6, 7, 8]);
+        image.patch_and_join(0x11A, &[9, 9]);
+        image.patch_and_join(0x11E, &[8, 8]);
+
+        assert_eq!(image.elements,
+                   vec![ImageElement::new(0x100, vec![0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 0, 0]),
+                        ImageElement::new(0x11A, vec![9, 9]),
+                        ImageElement::new(0x11E, vec![8, 8, 0, 0])]);
+    }
+}
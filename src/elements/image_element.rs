@@ -8,34 +8,92 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::io::Result;
-use byteorder::{BigEndian, LittleEndian, WriteBytesExt};
+#[cfg(feature = "std")]
+use std::io::{Error, ErrorKind, Read, Result};
+#[cfg(feature = "std")]
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
+use alloc::vec::Vec;
+
+use ::codec::{self, ParseLimits, SliceReader};
+
+/// A contiguous block of data to be written at `start_adress`
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ImageElement {
     pub start_adress: u32,
     pub data: Vec<u8>,
 }
 
 impl ImageElement {
+    /// Create an element holding `data` at `start_adress`
     pub fn new(start_adress: u32, data: Vec<u8>) -> ImageElement {
         ImageElement {
-            start_adress: start_adress,
-            data: data,
+            start_adress,
+            data,
         }
     }
+    /// Size in bytes of this element in a `DfuSe` file, header included
     pub fn size(&self) -> usize {
         8 + self.data.len()
     }
 
+    /// Address of the first byte after this element
+    pub fn end_adress(&self) -> u64 {
+        self.start_adress as u64 + self.data.len() as u64
+    }
+
+    /// The encoded element header, start address and size
+    pub(crate) fn header(&self) -> [u8; 8] {
+        let mut header = [0u8; 8];
+        header[..4].copy_from_slice(&self.start_adress.to_le_bytes());
+        header[4..].copy_from_slice(&(self.data.len() as u32).to_le_bytes());
+        header
+    }
+
+    /// Append the encoded element to `out`
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.header());
+        out.extend_from_slice(&self.data);
+    }
+
+    pub fn decode(reader: &mut SliceReader) -> codec::Result<ImageElement> {
+        ImageElement::decode_limited(reader, &ParseLimits::default())
+    }
+
+    pub(crate) fn decode_limited(reader: &mut SliceReader, limits: &ParseLimits) -> codec::Result<ImageElement> {
+        let start_adress = reader.read_u32()?;
+        let size = reader.read_u32()? as usize;
+        limits.check_element_size(size)?;
+        let data = reader.read_bytes(size)?;
+
+        Ok(ImageElement::new(start_adress, data.to_vec()))
+    }
+
+    #[cfg(feature = "std")]
     pub fn write_to<T: WriteBytesExt>(&self, buf: &mut T) -> Result<()> {
-        try!(buf.write_u32::<LittleEndian>(self.start_adress));
-        try!(buf.write_u32::<LittleEndian>(self.data.len() as u32));
+        buf.write_all(&self.header())?;
+        buf.write_all(&self.data)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn read_from<T: ReadBytesExt>(buf: &mut T) -> Result<ImageElement> {
+        ImageElement::read_limited(buf, &ParseLimits::default())
+    }
 
-        for b in self.data.as_slice().iter() {
-            try!(buf.write_u8(*b));
+    #[cfg(feature = "std")]
+    pub(crate) fn read_limited<T: ReadBytesExt>(buf: &mut T, limits: &ParseLimits) -> Result<ImageElement> {
+        let start_adress = buf.read_u32::<LittleEndian>()?;
+        let size = buf.read_u32::<LittleEndian>()?;
+        limits.check_element_size(size as usize)?;
+
+        // Don't trust the size before the data is really there
+        let mut data = Vec::new();
+        buf.take(size as u64).read_to_end(&mut data)?;
+        if data.len() != size as usize {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "truncated image element"));
         }
 
-        Ok(())
+        Ok(ImageElement::new(start_adress, data))
     }
 }
 
@@ -58,7 +116,23 @@ mod tests {
     }
 
     #[test]
-    #[ignore]
+    fn test_image_element_read_what_was_written() {
+        let element = ImageElement::new(0x08000000, vec![0x33, 0x44, 0x55]);
+        let mut buf = vec![];
+        element.write_to(&mut buf).unwrap();
+
+        assert_eq!(ImageElement::read_from(&mut &buf[..]).unwrap(), element);
+    }
+
+    #[test]
+    fn test_image_element_read_truncated() {
+        let buf = [0x00, 0x00, 0x00, 0x08, 0xFF, 0xFF, 0xFF, 0x7F, 0x01];
+        assert!(ImageElement::read_from(&mut &buf[..]).is_err());
+        assert_eq!(ImageElement::decode(&mut SliceReader::new(&buf)),
+                   Err(::codec::CodecError::UnexpectedEnd));
+    }
+
+    #[test]
     fn test_image_element_write_correct_data() {
         let element = ImageElement::new(0x008CFFFF, vec![0x33, 0x44, 0x55]);
         let mut buf: Vec<u8> = Vec::with_capacity(element.size());
@@ -71,11 +145,11 @@ mod tests {
         unsafe {
 
             // Check adress
-            assert_eq!([0x00, 0x8C, 0xFF, 0xFF], *(ptr as *const [u8; 4]));
+            assert_eq!([0xFF, 0xFF, 0x8C, 0x00], *(ptr as *const [u8; 4]));
             ptr = ptr.offset(4);
 
             // Check size
-            assert_eq!([0x00, 0x00, 0x00, 0x03], *(ptr as *const [u8; 4]));
+            assert_eq!([0x03, 0x00, 0x00, 0x00], *(ptr as *const [u8; 4]));
             ptr = ptr.offset(4);
 
             // Check data
@@ -8,9 +8,16 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::io::Result;
-use byteorder::{BigEndian, LittleEndian, WriteBytesExt};
+#[cfg(feature = "std")]
+use std::io::{Read, Result, Write};
 
+use alloc::vec::Vec;
+
+use ::codec::{self, CodecError, SliceReader};
+
+const SIGNATURE: [u8; 5] = [b'D', b'f', b'u', b'S', b'e'];
+
+/// The `DfuSe` prefix, starting every file
 pub struct Prefix {
     size: u32,
     nb_images: u8,
@@ -19,8 +26,8 @@ pub struct Prefix {
 impl Prefix {
     pub fn new(size: u32, nb_images: u8) -> Prefix {
         Prefix {
-            size: size,
-            nb_images: nb_images,
+            size,
+            nb_images,
         }
     }
 
@@ -28,18 +35,50 @@ impl Prefix {
         11
     }
 
-    pub fn write_to<T: WriteBytesExt>(&self, buf: &mut T) -> Result<()> {
+    /// Size of the whole file, as stored in the prefix
+    pub fn image_size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn nb_images(&self) -> u8 {
+        self.nb_images
+    }
+
+    /// Append the encoded prefix to `out`
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&SIGNATURE);
+        out.push(0x01u8);
+        out.extend_from_slice(&self.size.to_le_bytes());
+        out.push(self.nb_images);
+    }
+
+    pub fn decode(reader: &mut SliceReader) -> codec::Result<Prefix> {
+        if reader.read_bytes(SIGNATURE.len())? != SIGNATURE {
+            return Err(CodecError::Invalid("missing DfuSe signature"));
+        }
 
-        let signature: [u8; 5] = [b'D', b'f', b'u', b'S', b'e'];
-        for byte in &signature {
-            try!(buf.write_u8(byte.clone()));
+        if reader.read_u8()? != 0x01 {
+            return Err(CodecError::Invalid("unsupported DfuSe version"));
         }
-        try!(buf.write_u8(0x01u8));
-        try!(buf.write_u32::<LittleEndian>(self.size));
-        try!(buf.write_u8(self.nb_images));
 
-        Ok(())
+        let size = reader.read_u32()?;
+        let nb_images = reader.read_u8()?;
+
+        Ok(Prefix::new(size, nb_images))
+    }
+
+    #[cfg(feature = "std")]
+    pub fn write_to<T: Write>(&self, buf: &mut T) -> Result<()> {
+        let mut out = Vec::with_capacity(Prefix::size());
+        self.encode(&mut out);
+        buf.write_all(&out)
+    }
 
+    #[cfg(feature = "std")]
+    pub fn read_from<T: Read>(buf: &mut T) -> Result<Prefix> {
+        let mut raw = [0u8; 11];
+        buf.read_exact(&mut raw)?;
+        Ok(Prefix::decode(&mut SliceReader::new(&raw))?)
     }
 }
 
@@ -59,7 +98,23 @@ mod tests {
     }
 
     #[test]
-    #[ignore]
+    fn test_prefix_read_what_was_written() {
+        let prefix = Prefix::new(0x00FFAA55, 0x33);
+        let mut buf = vec![];
+        prefix.write_to(&mut buf).unwrap();
+
+        let read = Prefix::read_from(&mut &buf[..]).unwrap();
+        assert_eq!(read.size, 0x00FFAA55);
+        assert_eq!(read.nb_images(), 0x33);
+    }
+
+    #[test]
+    fn test_prefix_read_reject_bad_signature() {
+        let buf = b"DfuSx\x01\x00\x00\x00\x00\x00";
+        assert!(Prefix::read_from(&mut &buf[..]).is_err());
+    }
+
+    #[test]
     fn test_prefix_write_correct_data() {
         let prefix = Prefix::new(0x00FFAA55, 0x33);
         let mut buf = vec![];
@@ -77,7 +132,7 @@ mod tests {
             ptr = ptr.offset(1);
 
             // Check size
-            assert_eq!([0x00, 0xFF, 0xAA, 0x55], *(ptr as *const [u8; 4]));
+            assert_eq!([0x55, 0xAA, 0xFF, 0x00], *(ptr as *const [u8; 4]));
             ptr = ptr.offset(4);
 
             // Check number of image
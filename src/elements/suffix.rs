@@ -8,44 +8,130 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::io::Result;
-use byteorder::{LittleEndian, WriteBytesExt};
+#[cfg(feature = "std")]
+use std::io::{Error, ErrorKind, Result, Write};
 
+use alloc::vec::Vec;
+
+use ::codec::{self, CodecError};
+use ::version::BcdVersion;
+
+const DFU_VERSION: u16 = 0x011A;
+const SIGNATURE: [u8; 3] = [0x55, 0x46, 0x44];
+const LENGTH: usize = 16;
+
+/// The DFU suffix, identifying the device the file is meant for
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Suffix {
     pub fw_version: u16,
     pub usb_pid: u16,
     pub usb_vid: u16,
+    /// Vendor specific bytes, stored before the standard fields
+    pub vendor_data: Vec<u8>,
+}
+
+impl Default for Suffix {
+    fn default() -> Suffix {
+        Suffix::new()
+    }
 }
 
 // Warning: Suffix use Little Endian
 impl Suffix {
+    /// Largest suffix, CRC included, `bLength` being a byte
+    pub const MAX_LENGTH: usize = 255;
+
+    /// `bcdDFU` of every `DfuSe` file
+    pub const DFU_VERSION: u16 = DFU_VERSION;
+
     pub fn new() -> Suffix {
         Suffix {
             fw_version: 0xFFFF,
             usb_pid: 0xFFFF,
             usb_vid: 0xFFFF,
+            vendor_data: Vec::new(),
         }
     }
 
-    pub fn size() -> usize {
-        12 // Size without CRC
+    pub fn size(&self) -> usize {
+        self.vendor_data.len() + 12 // Size without CRC
+    }
+
+    /// The firmware version, `None` if `fw_version` is not BCD
+    pub fn bcd_version(&self) -> Option<BcdVersion> {
+        BcdVersion::from_bcd(self.fw_version)
     }
 
-    pub fn write_to<T: WriteBytesExt>(&self, buf: &mut T) -> Result<()> {
-        try!(buf.write_u16::<LittleEndian>(self.fw_version));
-        try!(buf.write_u16::<LittleEndian>(self.usb_pid));
-        try!(buf.write_u16::<LittleEndian>(self.usb_vid));
+    /// Append the encoded suffix, but the CRC, to `out`
+    ///
+    /// Fail if there is too much vendor data for `bLength`.
+    pub fn encode(&self, out: &mut Vec<u8>) -> codec::Result<()> {
+        if self.size() + 4 > Suffix::MAX_LENGTH {
+            return Err(CodecError::Invalid("too much DFU suffix vendor data"));
+        }
+
+        out.extend_from_slice(&self.vendor_data);
+        out.extend_from_slice(&self.fw_version.to_le_bytes());
+        out.extend_from_slice(&self.usb_pid.to_le_bytes());
+        out.extend_from_slice(&self.usb_vid.to_le_bytes());
 
         // DFU suffix version
-        try!(buf.write_u16::<LittleEndian>(0x011A));
+        out.extend_from_slice(&DFU_VERSION.to_le_bytes());
 
         // DFU suffix magic number
-        try!(buf.write_all(&[0x55, 0x46, 0x44]));
+        out.extend_from_slice(&SIGNATURE);
 
         // DFU suffix size with CRC
-        try!(buf.write_u8(16u8));
+        out.push((self.size() + 4) as u8);
         Ok(())
     }
+
+    /// Decode a suffix from `buf`, holding everything after the last target
+    /// but the CRC
+    ///
+    /// Like DFU tools do, the suffix is read backward from the end.
+    pub fn decode(buf: &[u8]) -> codec::Result<Suffix> {
+        if buf.len() < 12 {
+            return Err(CodecError::UnexpectedEnd);
+        }
+        let (vendor_data, buf) = buf.split_at(buf.len() - 12);
+        let u16_at = |n: usize| u16::from_le_bytes([buf[n], buf[n + 1]]);
+
+        if u16_at(6) != DFU_VERSION {
+            return Err(CodecError::Invalid("unsupported DFU version"));
+        }
+
+        if buf[8..11] != SIGNATURE {
+            return Err(CodecError::Invalid("missing DFU suffix signature"));
+        }
+
+        let length = buf[11] as usize;
+        if length < LENGTH || length != vendor_data.len() + LENGTH {
+            return Err(CodecError::Invalid("wrong DFU suffix length"));
+        }
+
+        Ok(Suffix {
+            fw_version: u16_at(0),
+            usb_pid: u16_at(2),
+            usb_vid: u16_at(4),
+            vendor_data: vendor_data.to_vec(),
+        })
+    }
+
+    #[cfg(feature = "std")]
+    pub fn write_to<T: Write>(&self, buf: &mut T) -> Result<()> {
+        let mut out = Vec::with_capacity(self.size());
+        if let Err(err) = self.encode(&mut out) {
+            return Err(Error::new(ErrorKind::InvalidInput, err));
+        }
+        buf.write_all(&out)
+    }
+
+    /// Read a suffix from `buf`, see `decode`
+    #[cfg(feature = "std")]
+    pub fn read_from(buf: &[u8]) -> Result<Suffix> {
+        Ok(Suffix::decode(buf)?)
+    }
 }
 
 
@@ -55,7 +141,7 @@ mod tests {
     use super::*;
 
     fn assert_write_reported_size(suffix: Suffix) {
-        let reported = Suffix::size();
+        let reported = suffix.size();
         let mut buf: Vec<u8> = Vec::with_capacity(reported);
         suffix.write_to(&mut buf).unwrap();
         assert_eq!(reported, buf.len());
@@ -68,7 +154,28 @@ mod tests {
             fw_version: 0x3344,
             usb_pid: 0x4433,
             usb_vid: 0xFF00,
+            vendor_data: vec![],
         });
+        assert_write_reported_size(Suffix {
+            vendor_data: vec![0x01, 0x02, 0x03],
+            ..Suffix::new()
+        });
+    }
+
+    #[test]
+    fn test_suffix_read_what_was_written() {
+        let suffix = Suffix {
+            fw_version: 0x3344,
+            usb_pid: 0x4433,
+            usb_vid: 0xFF00,
+            vendor_data: vec![0x01, 0x02, 0x03],
+        };
+        let mut buf = vec![];
+        suffix.write_to(&mut buf).unwrap();
+
+        assert_eq!(buf[buf.len() - 1], 19);
+        assert_eq!(Suffix::read_from(&buf).unwrap(), suffix);
+        assert!(Suffix::read_from(&buf[1..]).is_err());
     }
 
     #[test]
@@ -77,9 +184,10 @@ mod tests {
             fw_version: 0x3344,
             usb_pid: 0x4433,
             usb_vid: 0xFF00,
+            vendor_data: vec![],
         };
 
-        let mut buf: Vec<u8> = Vec::with_capacity(Suffix::size());
+        let mut buf: Vec<u8> = Vec::with_capacity(suffix.size());
 
         suffix.write_to(&mut buf).unwrap();
 
@@ -8,9 +8,21 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::io::Result;
-use byteorder::{BigEndian, LittleEndian, WriteBytesExt};
+#[cfg(feature = "std")]
+use std::io::{Read, Result, Write};
 
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use ::codec::{self, CodecError, SliceReader};
+
+const SIGNATURE: [u8; 6] = [b'T', b'a', b'r', b'g', b'e', b't'];
+
+/// The prefix of a target, followed by its elements
+///
+/// As in UM0391: `Target` signature, bAlternateSetting, bTargetNamed as a
+/// DWORD, 255 bytes of null padded name, dwTargetSize and dwNbElements, all
+/// little endian.
 pub struct TargetPrefix {
     name: Option<String>,
     alternate: u8,
@@ -25,10 +37,10 @@ impl TargetPrefix {
                nb_elements: u32)
                -> TargetPrefix {
         TargetPrefix {
-            name: name,
-            alternate: alternate,
-            image_size: image_size,
-            nb_elements: nb_elements,
+            name,
+            alternate,
+            image_size,
+            nb_elements,
         }
     }
 
@@ -36,47 +48,79 @@ impl TargetPrefix {
         274
     }
 
-    pub fn write_to<T: WriteBytesExt>(&self, buf: &mut T) -> Result<()> {
-        let signature: [u8; 6] = [b'T', b'a', b'r', b'g', b'e', b't'];
-        for byte in &signature {
-            try!(buf.write_u8(byte.clone()));
+    pub fn name(&self) -> Option<&String> {
+        self.name.as_ref()
+    }
+
+    pub fn alternate(&self) -> u8 {
+        self.alternate
+    }
+
+    pub fn image_size(&self) -> u32 {
+        self.image_size
+    }
+
+    pub fn nb_elements(&self) -> u32 {
+        self.nb_elements
+    }
+
+    /// Append the encoded target prefix to `out`
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&SIGNATURE);
+        out.push(self.alternate);
+
+        // bTargetNamed is a little endian boolean DWORD
+        out.extend_from_slice(&(self.name.is_some() as u32).to_le_bytes());
+
+        // Longer names are truncated
+        let mut raw_name = [0u8; 255];
+        if let Some(ref txt) = self.name {
+            let len = txt.len().min(raw_name.len());
+            raw_name[..len].copy_from_slice(&txt.as_bytes()[..len]);
         }
-        try!(buf.write_u8(self.alternate));
-
-        match self.name {
-            Some(ref txt) => {
-                // If this target is named, we should write a boolean value here
-                // Spec say boolan adress is 7..11 -> u32
-                try!(buf.write_u32::<BigEndian>(0x01));
-
-                let mut i = 11;
-                for c in txt.as_bytes().iter() {
-                    if i < 266 {
-                        try!(buf.write_u8(*c));
-                        i += 1;
-                    } else {
-                        break;
-                    }
-                }
-                while i < 266 {
-                    try!(buf.write_u8(0u8));
-                    i += 1;
-                }
-            }
-            None => {
-                // Again the same note, it's a boolean in a u32
-                // It's a byte efficient file format ;)
-                try!(buf.write_u32::<BigEndian>(0x00));
-
-                for _ in 11..266 {
-                    try!(buf.write_u8(0u8));
-                }
-            }
+        out.extend_from_slice(&raw_name);
+
+        out.extend_from_slice(&self.image_size.to_le_bytes());
+        out.extend_from_slice(&self.nb_elements.to_le_bytes());
+    }
+
+    pub fn decode(reader: &mut SliceReader) -> codec::Result<TargetPrefix> {
+        if reader.read_bytes(SIGNATURE.len())? != SIGNATURE {
+            return Err(CodecError::Invalid("missing Target signature"));
         }
 
-        try!(buf.write_u32::<LittleEndian>(self.image_size));
-        try!(buf.write_u32::<LittleEndian>(self.nb_elements));
-        Ok(())
+        let alternate = reader.read_u8()?;
+
+        // Any non zero value mean that the target is named, which also reads
+        // files where it was written big endian
+        let named = reader.read_u32()? != 0;
+
+        let raw_name = reader.read_bytes(255)?;
+        let name = if named {
+            let len = raw_name.iter().position(|c| *c == 0).unwrap_or(raw_name.len());
+            Some(String::from_utf8_lossy(&raw_name[..len]).into_owned())
+        } else {
+            None
+        };
+
+        let image_size = reader.read_u32()?;
+        let nb_elements = reader.read_u32()?;
+
+        Ok(TargetPrefix::new(name, alternate, image_size, nb_elements))
+    }
+
+    #[cfg(feature = "std")]
+    pub fn write_to<T: Write>(&self, buf: &mut T) -> Result<()> {
+        let mut out = Vec::with_capacity(TargetPrefix::size());
+        self.encode(&mut out);
+        buf.write_all(&out)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn read_from<T: Read>(buf: &mut T) -> Result<TargetPrefix> {
+        let mut raw = [0u8; 274];
+        buf.read_exact(&mut raw)?;
+        Ok(TargetPrefix::decode(&mut SliceReader::new(&raw))?)
     }
 }
 
@@ -110,38 +154,61 @@ mod tests {
     }
 
     #[test]
-    #[ignore]
+    fn test_target_prefix_read_what_was_written() {
+        for name in [None, Some("ST...".to_string())] {
+            let prefix = TargetPrefix::new(name.clone(), 0xAB, 0x00FFFF00, 0x3355AA00);
+            let mut buf = vec![];
+            prefix.write_to(&mut buf).unwrap();
+
+            let read = TargetPrefix::read_from(&mut &buf[..]).unwrap();
+            assert_eq!(read.name(), name.as_ref());
+            assert_eq!(read.alternate(), 0xAB);
+            assert_eq!(read.image_size(), 0x00FFFF00);
+            assert_eq!(read.nb_elements(), 0x3355AA00);
+        }
+    }
+
+    #[test]
     fn test_target_prefix_write_correct_data() {
-        let prefix = TargetPrefix::new(Some("ABCD".to_string()), 0xAB, 0x00FFFF00, 0x3355AA00);
+        let prefix = TargetPrefix::new(Some("ABCD".to_string()), 0xAB, 0x00FFFF01, 0x3355AA00);
         let mut buf = vec![];
         prefix.write_to(&mut buf).unwrap();
+        assert_eq!(buf.len(), 274);
 
-        let mut ptr = buf.as_mut_ptr();
-        unsafe {
+        // Signature and alternate setting
+        assert_eq!(&buf[0..6], b"Target");
+        assert_eq!(buf[6], 0xAB);
 
-            // Check signature
-            assert_eq!(b"Target", &*(ptr as *const [u8; 6]));
-            ptr = ptr.offset(6);
+        // bTargetNamed
+        assert_eq!(&buf[7..11], &[0x01, 0x00, 0x00, 0x00]);
 
-            // Check alternate byte
-            assert_eq!(0xAB, *(ptr));
-            ptr = ptr.offset(1);
+        // Null padded name
+        assert_eq!(&buf[11..16], &[b'A', b'B', b'C', b'D', 0x00]);
+        assert!(buf[16..266].iter().all(|&b| b == 0x00));
 
-            // Check this 32bit boolean
-            assert_eq!([0x00, 0x00, 0x00, 0x01], *(ptr as *const [u8; 4]));
-            ptr = ptr.offset(4);
+        // dwTargetSize and dwNbElements
+        assert_eq!(&buf[266..270], &[0x01, 0xFF, 0xFF, 0x00]);
+        assert_eq!(&buf[270..274], &[0x00, 0xAA, 0x55, 0x33]);
+    }
 
-            // Check the name
-            assert_eq!([b'A', b'B', b'C', b'D', 0x0], *(ptr as *const [u8; 5]));
-            ptr = ptr.offset(255);
+    #[test]
+    fn test_target_prefix_unnamed_data() {
+        let prefix = TargetPrefix::new(None, 0x01, 0x10, 0x01);
+        let mut buf = vec![];
+        prefix.encode(&mut buf);
 
-            // Check the size
-            assert_eq!([0x00, 0xFF, 0xFF, 0x00], *(ptr as *const [u8; 4]));
-            ptr = ptr.offset(4);
+        assert_eq!(&buf[7..11], &[0x00, 0x00, 0x00, 0x00]);
+        assert!(buf[11..266].iter().all(|&b| b == 0x00));
+        assert_eq!(&buf[266..274], &[0x10, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00]);
+    }
 
-            // Check the number of image_size
-            assert_eq!([0x33, 0x55, 0xAA, 0x00], *(ptr as *const [u8; 4]));
-        }
+    #[test]
+    fn test_target_prefix_read_big_endian_flag() {
+        let mut buf = vec![];
+        TargetPrefix::new(Some("ABCD".to_string()), 0x00, 0x00, 0x00).encode(&mut buf);
+        buf[7..11].copy_from_slice(&[0x00, 0x00, 0x00, 0x01]);
 
+        let read = TargetPrefix::decode(&mut SliceReader::new(&buf)).unwrap();
+        assert_eq!(read.name().map(|s| s.as_str()), Some("ABCD"));
     }
 }
@@ -0,0 +1,371 @@
+// Copyright © 2016 - Samuel Dolt <samuel@dolt.ch>
+//
+// See the COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! AES encryption of element data, available with the `encryption` feature
+//!
+//! Only the element data is encrypted, prefixes, element headers and suffix
+//! are kept in clear so `DfuSe` addressing still works.
+//!
+//! The IV of an element is the 12 bytes nonce followed by a big endian
+//! 32 bit value derived from `start_adress`:
+//!
+//! - in CTR mode, it's the counter of the 16 bytes block holding
+//!   `start_adress`, that is `start_adress / 16`, and the key stream start
+//!   at `start_adress % 16`. Every address is encrypted by the same key
+//!   stream, whatever the element holding it.
+//! - in CBC mode, it's `start_adress`. Data is padded with `0xFF` to a
+//!   multiple of 16 bytes, the padding is kept on decryption.
+//!
+//! The nonce must be unique for every file encrypted with a key: two files
+//! sharing key and nonce share their key stream, and XOR-ing them gives the
+//! XOR of their data. A random nonce per release is fine. It is not secret,
+//! `write_encrypted_to` stores it at the end of the suffix vendor data, after
+//! the `AESN` tag, and `read_encrypted_from` reads it back.
+//!
+//! # Examples
+//!
+//! ```
+//! use dfuse::DfuseFile;
+//! use dfuse::encryption::{Cipher, Mode};
+//!
+//! let mut file = DfuseFile::new();
+//! file.add_image("Internal Flash", 0, 0x08000000, vec![0x00; 0x100]);
+//!
+//! let nonce = [0x4E, 0x31, 0x9A, 0x07, 0xC2, 0x55, 0x18, 0xE3, 0x70, 0x0B, 0x6D, 0xA4];
+//! let cipher = Cipher::aes128([0x2B; 16], nonce, Mode::Ctr).only_alternate(0);
+//!
+//! let mut buf = vec![];
+//! file.write_encrypted_to(&mut buf, &cipher).unwrap();
+//!
+//! let read = DfuseFile::read_encrypted_from(&mut &buf[..], &cipher).unwrap();
+//! assert_eq!(read, file);
+//! ```
+
+use std::io::{Error, ErrorKind, Read, Result, Write};
+
+use aes::{Aes128, Aes256};
+use cbc::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit, StreamCipher, StreamCipherSeek};
+use cbc::cipher::block_padding::NoPadding;
+
+use ::elements::ImageElement;
+use ::file::DfuseFile;
+use ::flash::ERASED;
+
+const BLOCK_SIZE: usize = 16;
+
+/// Tag of the nonce stored in the suffix vendor data
+const NONCE_TAG: [u8; 4] = [b'A', b'E', b'S', b'N'];
+
+/// Block cipher mode of operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Ctr,
+    Cbc,
+}
+
+#[derive(Clone)]
+enum Key {
+    Aes128([u8; 16]),
+    Aes256([u8; 32]),
+}
+
+/// Key and parameters used to encrypt element data
+#[derive(Clone)]
+pub struct Cipher {
+    key: Key,
+    mode: Mode,
+    nonce: [u8; 12],
+    alternates: Option<Vec<u8>>,
+}
+
+impl Cipher {
+    /// AES-128 cipher for every target, `nonce` being the first 12 bytes of
+    /// every IV and unique for this key
+    pub fn aes128(key: [u8; 16], nonce: [u8; 12], mode: Mode) -> Cipher {
+        Cipher {
+            key: Key::Aes128(key),
+            mode,
+            nonce,
+            alternates: None,
+        }
+    }
+
+    /// AES-256 cipher for every target, `nonce` being the first 12 bytes of
+    /// every IV and unique for this key
+    pub fn aes256(key: [u8; 32], nonce: [u8; 12], mode: Mode) -> Cipher {
+        Cipher {
+            key: Key::Aes256(key),
+            mode,
+            nonce,
+            alternates: None,
+        }
+    }
+
+    pub fn nonce(&self) -> [u8; 12] {
+        self.nonce
+    }
+
+    /// Only encrypt the targets using `alternate`, can be called more than once
+    pub fn only_alternate(mut self, alternate: u8) -> Cipher {
+        self.alternates.get_or_insert_with(Vec::new).push(alternate);
+        self
+    }
+
+    fn applies_to(&self, alternate: u8) -> bool {
+        match self.alternates {
+            Some(ref alternates) => alternates.contains(&alternate),
+            None => true,
+        }
+    }
+
+    fn iv(&self, value: u32) -> [u8; 16] {
+        let mut iv = [0u8; 16];
+        iv[..12].copy_from_slice(&self.nonce);
+        iv[12..].copy_from_slice(&value.to_be_bytes());
+        iv
+    }
+
+    fn apply_ctr(&self, start_adress: u32, data: &mut [u8]) {
+        let iv = self.iv(start_adress / BLOCK_SIZE as u32);
+        let offset = start_adress as usize % BLOCK_SIZE;
+
+        match self.key {
+            Key::Aes128(ref key) => {
+                let mut c = ctr::Ctr32BE::<Aes128>::new(key.into(), &iv.into());
+                c.seek(offset);
+                c.apply_keystream(data);
+            }
+            Key::Aes256(ref key) => {
+                let mut c = ctr::Ctr32BE::<Aes256>::new(key.into(), &iv.into());
+                c.seek(offset);
+                c.apply_keystream(data);
+            }
+        }
+    }
+
+    /// Encrypt the data of `element`
+    pub fn encrypt(&self, element: &ImageElement) -> ImageElement {
+        let mut data = element.data.clone();
+
+        match self.mode {
+            Mode::Ctr => self.apply_ctr(element.start_adress, &mut data),
+            Mode::Cbc => {
+                let len = data.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+                data.resize(len, ERASED);
+
+                let iv = self.iv(element.start_adress);
+                // Can't fail, data is a multiple of the block size
+                let _ = match self.key {
+                    Key::Aes128(ref key) => {
+                        cbc::Encryptor::<Aes128>::new(key.into(), &iv.into())
+                            .encrypt_padded_mut::<NoPadding>(&mut data, len)
+                            .map(|_| ())
+                    }
+                    Key::Aes256(ref key) => {
+                        cbc::Encryptor::<Aes256>::new(key.into(), &iv.into())
+                            .encrypt_padded_mut::<NoPadding>(&mut data, len)
+                            .map(|_| ())
+                    }
+                };
+            }
+        }
+
+        ImageElement::new(element.start_adress, data)
+    }
+
+    /// Decrypt the data of `element`
+    pub fn decrypt(&self, element: &ImageElement) -> Result<ImageElement> {
+        let mut data = element.data.clone();
+
+        match self.mode {
+            Mode::Ctr => self.apply_ctr(element.start_adress, &mut data),
+            Mode::Cbc => {
+                let iv = self.iv(element.start_adress);
+                let res = match self.key {
+                    Key::Aes128(ref key) => {
+                        cbc::Decryptor::<Aes128>::new(key.into(), &iv.into())
+                            .decrypt_padded_mut::<NoPadding>(&mut data)
+                            .map(|_| ())
+                    }
+                    Key::Aes256(ref key) => {
+                        cbc::Decryptor::<Aes256>::new(key.into(), &iv.into())
+                            .decrypt_padded_mut::<NoPadding>(&mut data)
+                            .map(|_| ())
+                    }
+                };
+                if res.is_err() {
+                    return Err(Error::new(ErrorKind::InvalidData,
+                                          "encrypted element is not a multiple of 16 bytes"));
+                }
+            }
+        }
+
+        Ok(ImageElement::new(element.start_adress, data))
+    }
+}
+
+impl DfuseFile {
+    /// Same as `write_to`, but the element data is encrypted with `cipher`
+    ///
+    /// The nonce is appended to the suffix vendor data. Fail if elements
+    /// padded by `Mode::Cbc` overlap the next ones.
+    pub fn write_encrypted_to<T: Write>(&self, buf: &mut T, cipher: &Cipher) -> Result<()> {
+        let mut encrypted = self.clone();
+        let vendor_data = &mut encrypted.suffix_mut().vendor_data;
+        vendor_data.extend_from_slice(&NONCE_TAG);
+        vendor_data.extend_from_slice(&cipher.nonce);
+
+        for image in encrypted.images_mut().iter_mut().filter(|i| cipher.applies_to(i.alternate)) {
+            image.elements = image.elements.iter().map(|e| cipher.encrypt(e)).collect();
+
+            if let Some(adress) = image.find_overlap() {
+                return Err(Error::new(ErrorKind::InvalidInput,
+                                      format!("encrypted elements overlap at 0x{:08X}", adress)));
+            }
+        }
+
+        encrypted.write_to(buf)
+    }
+
+    /// Same as `read_from`, but the element data is decrypted with `cipher`
+    ///
+    /// The nonce stored in the suffix vendor data is used instead of the one
+    /// of `cipher`, and removed from the vendor data.
+    pub fn read_encrypted_from<T: Read>(buf: &mut T, cipher: &Cipher) -> Result<DfuseFile> {
+        let mut file = DfuseFile::read_from(buf)?;
+
+        let vendor_data = &mut file.suffix_mut().vendor_data;
+        let tagged = NONCE_TAG.len() + 12;
+        if vendor_data.len() < tagged ||
+           vendor_data[vendor_data.len() - tagged..][..NONCE_TAG.len()] != NONCE_TAG {
+            return Err(Error::new(ErrorKind::InvalidData, "no nonce in the DFU suffix"));
+        }
+        let mut nonce = [0u8; 12];
+        nonce.copy_from_slice(&vendor_data[vendor_data.len() - 12..]);
+        let len = vendor_data.len() - tagged;
+        vendor_data.truncate(len);
+
+        let cipher = Cipher { nonce, ..cipher.clone() };
+        for image in file.images_mut().iter_mut().filter(|i| cipher.applies_to(i.alternate)) {
+            let mut elements = Vec::with_capacity(image.elements.len());
+            for element in &image.elements {
+                elements.push(cipher.decrypt(element)?);
+            }
+            image.elements = elements;
+        }
+
+        Ok(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_hex(txt: &str) -> Vec<u8> {
+        (0..txt.len() / 2).map(|i| u8::from_str_radix(&txt[2 * i..2 * i + 2], 16).unwrap()).collect()
+    }
+
+    fn plain() -> ImageElement {
+        ImageElement::new(0x08000004, (0u8..20).collect())
+    }
+
+    fn cipher(mode: Mode) -> Cipher {
+        Cipher::aes128([0x2B; 16], [0xF0; 12], mode)
+    }
+
+    #[test]
+    fn ctr_test_vector() {
+        let encrypted = cipher(Mode::Ctr).encrypt(&plain());
+        assert_eq!(encrypted.data,
+                   from_hex("b718a82163099075784a2e35d6140e7cf2f2e575"));
+        assert_eq!(cipher(Mode::Ctr).decrypt(&encrypted).unwrap(), plain());
+    }
+
+    #[test]
+    fn ctr_key_stream_follow_adress() {
+        let whole = cipher(Mode::Ctr).encrypt(&plain());
+        let tail = cipher(Mode::Ctr).encrypt(&ImageElement::new(0x08000010, (12u8..20).collect()));
+        assert_eq!(&whole.data[12..], &tail.data[..]);
+    }
+
+    #[test]
+    fn ctr_key_stream_follow_nonce() {
+        let other = Cipher::aes128([0x2B; 16], [0xF1; 12], Mode::Ctr);
+        assert!(other.encrypt(&plain()).data != cipher(Mode::Ctr).encrypt(&plain()).data);
+    }
+
+    #[test]
+    fn cbc_test_vector() {
+        let encrypted = Cipher::aes256([0x2B; 32], [0x00; 12], Mode::Cbc).encrypt(&plain());
+        assert_eq!(encrypted.data,
+                   from_hex("7778fd83e2305b6e6ca03d3d8dbe9801\
+                             56bdd8cf94d09e006121842d396506e7"));
+
+        let mut padded = plain();
+        padded.data.resize(32, 0xFF);
+        assert_eq!(Cipher::aes256([0x2B; 32], [0x00; 12], Mode::Cbc).decrypt(&encrypted).unwrap(), padded);
+    }
+
+    #[test]
+    fn cbc_reject_partial_block() {
+        assert!(cipher(Mode::Cbc).decrypt(&plain()).is_err());
+    }
+
+    #[test]
+    fn headers_are_kept_in_clear() {
+        let mut file = DfuseFile::new();
+        file.add_image("Internal Flash", 0, 0x08000000, vec![0x00; 0x20]);
+        file.add_image("Option Bytes", 1, 0x1FFFF800, vec![0xAA, 0x55]);
+        let cipher = cipher(Mode::Cbc).only_alternate(0);
+
+        let mut buf = vec![];
+        file.write_encrypted_to(&mut buf, &cipher).unwrap();
+        let raw = DfuseFile::read_from(&mut &buf[..]).unwrap();
+        assert_eq!(&raw.suffix().vendor_data[..4], b"AESN");
+        assert_eq!(&raw.suffix().vendor_data[4..], &[0xF0; 12]);
+        assert_eq!(raw.images()[0].elements[0].start_adress, 0x08000000);
+        assert!(raw.images()[0].elements[0].data != file.images()[0].elements[0].data);
+        assert_eq!(raw.images()[1], file.images()[1]);
+
+        assert_eq!(DfuseFile::read_encrypted_from(&mut &buf[..], &cipher).unwrap(), file);
+    }
+
+    #[test]
+    fn cbc_reject_overlapping_padding() {
+        let mut file = DfuseFile::new();
+        file.add_image("Internal Flash", 0, 0x08000000, vec![0x00; 4]);
+        file.images_mut()[0].elements.push(ImageElement::new(0x08000004, vec![0x01; 4]));
+
+        let mut buf = vec![];
+        let err = file.write_encrypted_to(&mut buf, &cipher(Mode::Cbc)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert!(buf.is_empty());
+
+        file.write_encrypted_to(&mut buf, &cipher(Mode::Ctr)).unwrap();
+    }
+
+    #[test]
+    fn read_with_stored_nonce() {
+        let mut file = DfuseFile::new();
+        file.add_image("Internal Flash", 0, 0x08000000, vec![0x00; 0x20]);
+        file.suffix_mut().vendor_data = vec![0x42];
+
+        let mut buf = vec![];
+        file.write_encrypted_to(&mut buf, &cipher(Mode::Ctr)).unwrap();
+
+        let reader = Cipher::aes128([0x2B; 16], [0x00; 12], Mode::Ctr);
+        assert_eq!(DfuseFile::read_encrypted_from(&mut &buf[..], &reader).unwrap(), file);
+
+        let mut clear = vec![];
+        file.write_to(&mut clear).unwrap();
+        assert!(DfuseFile::read_encrypted_from(&mut &clear[..], &reader).is_err());
+    }
+}
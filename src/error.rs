@@ -0,0 +1,98 @@
+// Copyright © 2016 - Samuel Dolt <samuel@dolt.ch>
+//
+// See the COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::error;
+use std::fmt;
+use std::io;
+
+/// Error returned by operations working on the content of a `DfuseFile`
+#[derive(Debug)]
+pub enum Error {
+    /// An I/O error
+    Io(io::Error),
+    /// An address is outside of the memory it should belong to
+    Unmapped(u32),
+    /// Two targets use the same alternate setting
+    AlternateConflict(u8),
+    /// Two elements of the target for `alternate` write at `adress`
+    Overlap { alternate: u8, adress: u32 },
+    /// An address range ending before its start
+    InvalidRange { start: u32, end: u64 },
+    /// A key can't be decoded
+    InvalidKey,
+    /// No signature where it was expected
+    MissingSignature,
+    /// The signature doesn't match the content
+    BadSignature,
+    /// No such part in the device database
+    UnknownPart(String),
+    /// The device has no memory for this alternate setting
+    UnknownAlternate(u8),
+    /// The file is meant for another USB device
+    WrongDevice { vendor_id: u16, product_id: u16 },
+    /// A required value was not given
+    MissingField(&'static str),
+    /// The file can't be written as is
+    InvalidFile(&'static str),
+    /// A CRC whose width is not between 8 and 32 bits
+    UnsupportedCrcWidth(u8),
+    /// A provisioning unit can't be used
+    InvalidUnit { serial: String, reason: &'static str },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref err) => err.fmt(f),
+            Error::Unmapped(adress) => write!(f, "address 0x{:08X} is not mapped", adress),
+            Error::AlternateConflict(alternate) => {
+                write!(f, "alternate {} is used by more than one target", alternate)
+            }
+            Error::Overlap { alternate, adress } => {
+                write!(f,
+                       "elements of alternate {} overlap at address 0x{:08X}",
+                       alternate,
+                       adress)
+            }
+            Error::InvalidRange { start, end } => {
+                write!(f, "invalid address range 0x{:08X}..0x{:08X}", start, end)
+            }
+            Error::InvalidKey => f.write_str("invalid key"),
+            Error::MissingSignature => f.write_str("missing signature"),
+            Error::BadSignature => f.write_str("bad signature"),
+            Error::UnknownPart(ref part) => write!(f, "unknown part {}", part),
+            Error::UnknownAlternate(alternate) => write!(f, "no memory for alternate {}", alternate),
+            Error::WrongDevice { vendor_id, product_id } => {
+                write!(f, "file is for USB device {:04X}:{:04X}", vendor_id, product_id)
+            }
+            Error::MissingField(field) => write!(f, "no {} given", field),
+            Error::InvalidFile(msg) => f.write_str(msg),
+            Error::UnsupportedCrcWidth(width) => write!(f, "unsupported {} bit CRC", width),
+            Error::InvalidUnit { ref serial, reason } => write!(f, "unit {:?}: {}", serial, reason),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+pub type Result<T> = ::std::result::Result<T, Error>;
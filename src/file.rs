@@ -10,12 +10,23 @@
 
 use ::elements::*;
 
-use ::std::io::Write;
-use ::tools::BufWriterWithCRC;
-use ::std::io::Result;
+#[cfg(feature = "std")]
+use ::std::io::{self, Read, Write};
+#[cfg(feature = "std")]
+use ::tools::{BufWriterWithCRC, CRCParams, ReaderWithCRC, WriterWithCRC};
+#[cfg(feature = "std")]
+use ::std::io::{Error, ErrorKind, Result};
 
+#[cfg(feature = "std")]
 use ::byteorder::LittleEndian;
 
+use alloc::string::ToString;
+use alloc::vec::Vec;
+
+use ::codec::{self, CodecError, ParseLimits, SliceReader};
+use ::tools::CRC;
+use ::version::BcdVersion;
+
 const CRC_SIZE: usize = 0x4;
 
 /// A struct representing a DFU file
@@ -31,11 +42,31 @@ const CRC_SIZE: usize = 0x4;
 ///
 /// let empty_dfy = DfuseFile::new();
 /// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DfuseFile {
     images: Vec<Image>,
     suffix: Suffix,
 }
 
+/// CRC stored in a file and CRC of its content
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CrcCheck {
+    pub stored: u32,
+    pub computed: u32,
+}
+
+impl CrcCheck {
+    pub fn is_valid(&self) -> bool {
+        self.stored == self.computed
+    }
+}
+
+impl Default for DfuseFile {
+    fn default() -> DfuseFile {
+        DfuseFile::new()
+    }
+}
+
 impl DfuseFile {
     /// Create an empty `DfuseFile`
     pub fn new() -> DfuseFile {
@@ -48,13 +79,13 @@ impl DfuseFile {
     /// Add a named binary image
     pub fn add_image(&mut self, name: &str, alternate: u8, start_adress: u32, data: Vec<u8>) {
         let element = ImageElement {
-            start_adress: start_adress,
-            data: data,
+            start_adress,
+            data,
         };
 
         let image = Image {
             name: Some(name.to_string()),
-            alternate: alternate,
+            alternate,
             elements: vec![element],
         };
 
@@ -65,13 +96,13 @@ impl DfuseFile {
     /// Add a unamed binary image
     pub fn add_unamed_image(&mut self, alternate: u8, start_adress: u32, data: Vec<u8>) {
         let element = ImageElement {
-            start_adress: start_adress,
-            data: data,
+            start_adress,
+            data,
         };
 
         let image = Image {
             name: None,
-            alternate: alternate,
+            alternate,
             elements: vec![element],
         };
 
@@ -90,30 +121,211 @@ impl DfuseFile {
         self.suffix.fw_version = ver;
     }
 
+    /// Set the firmware version from a `BcdVersion`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dfuse::DfuseFile;
+    ///
+    /// let mut file = DfuseFile::new();
+    /// file.set_bcd_version("2.3.1".parse().unwrap());
+    /// assert_eq!(file.suffix().fw_version, 0x0231);
+    /// ```
+    pub fn set_bcd_version(&mut self, version: BcdVersion) {
+        self.suffix.fw_version = version.to_bcd();
+    }
+
+    /// Images stored in this file, in file order
+    pub fn images(&self) -> &[Image] {
+        &self.images
+    }
+
+    /// Mutable access to the images stored in this file
+    pub fn images_mut(&mut self) -> &mut Vec<Image> {
+        &mut self.images
+    }
+
+    /// The DFU suffix of this file
+    pub fn suffix(&self) -> &Suffix {
+        &self.suffix
+    }
+
+    /// Mutable access to the DFU suffix of this file
+    pub fn suffix_mut(&mut self) -> &mut Suffix {
+        &mut self.suffix
+    }
+
     pub fn size(&self) -> usize {
 
-        self.images.iter().fold(Prefix::size() + Suffix::size() + CRC_SIZE,
+        self.images.iter().fold(Prefix::size() + self.suffix.size() + CRC_SIZE,
                                 |sum, x| sum + x.size())
     }
 
+    /// Prefix of this file, `None` if it holds more than 255 targets
+    pub(crate) fn prefix(&self) -> Option<Prefix> {
+        if self.images.len() > u8::MAX as usize {
+            return None;
+        }
+        Some(Prefix::new(self.size() as u32, self.images.len() as u8))
+    }
+
+    /// Encode this file, CRC included
+    ///
+    /// Fail if there are more than 255 targets or too much vendor data in the
+    /// suffix.
+    pub fn to_bytes(&self) -> codec::Result<Vec<u8>> {
+        let prefix = self.prefix().ok_or(CodecError::Invalid("more than 255 targets"))?;
+        let mut out = Vec::with_capacity(self.size());
+        prefix.encode(&mut out);
+        for image in &self.images {
+            image.encode(&mut out);
+        }
+        self.suffix.encode(&mut out)?;
+
+        let mut crc = CRC::new_jam();
+        crc.update(&out);
+        out.extend_from_slice(&crc.finalize().to_le_bytes());
+        Ok(out)
+    }
+
+    /// Decode a file held in `buf`, without rejecting a bad CRC
+    pub fn from_bytes(buf: &[u8]) -> codec::Result<(DfuseFile, CrcCheck)> {
+        DfuseFile::from_bytes_limited(buf, &ParseLimits::default())
+    }
+
+    /// Decode a file held in `buf`, without rejecting a bad CRC, checking
+    /// `limits`
+    pub fn from_bytes_limited(buf: &[u8], limits: &ParseLimits) -> codec::Result<(DfuseFile, CrcCheck)> {
+        limits.check_size(buf.len())?;
+        let mut reader = SliceReader::new(buf);
+
+        let prefix = Prefix::decode(&mut reader)?;
+        limits.check_targets(prefix.nb_images() as usize)?;
+
+        let mut images = Vec::new();
+        for _ in 0..prefix.nb_images() {
+            images.push(Image::decode_limited(&mut reader, limits)?);
+        }
+
+        // Everything left is the suffix, which is at most 255 bytes
+        let tail = reader.remaining();
+        if tail.len() > Suffix::MAX_LENGTH {
+            return Err(CodecError::Invalid("trailing data after DFU suffix"));
+        }
+        if tail.len() < CRC_SIZE {
+            return Err(CodecError::UnexpectedEnd);
+        }
+        let suffix = Suffix::decode(&tail[..tail.len() - CRC_SIZE])?;
+
+        let (content, stored) = buf.split_at(buf.len() - CRC_SIZE);
+        let mut crc = CRC::new_jam();
+        crc.update(content);
+        let check = CrcCheck {
+            stored: u32::from_le_bytes([stored[0], stored[1], stored[2], stored[3]]),
+            computed: crc.finalize(),
+        };
+
+        Ok((DfuseFile { images, suffix }, check))
+    }
+
+    #[cfg(feature = "std")]
     pub fn write_to<T: Write>(&self, buf: &mut T) -> Result<()> {
         let mut buf = BufWriterWithCRC::new(buf);
 
-        let prefix = Prefix::new(self.size() as u32, self.images.len() as u8);
-        try!(prefix.write_to(&mut buf));
+        self.write_content(&mut buf)?;
+
+        // CRC is documented in the suffix section as a little endian 32bit unsigned integer
+        buf.write_crc::<LittleEndian>()?;
+
+        buf.flush()?;
+
+        Ok(())
+    }
+
+    /// Everything but the CRC
+    #[cfg(feature = "std")]
+    fn write_content<T: Write>(&self, buf: &mut T) -> Result<()> {
+        let prefix = self.prefix()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "more than 255 targets"))?;
+        prefix.write_to(buf)?;
 
         for image in &self.images {
-            try!(image.write_to(&mut buf));
+            image.write_to(buf)?;
         }
 
-        try!(self.suffix.write_to(&mut buf));
+        self.suffix.write_to(buf)
+    }
+
+    /// CRC written at the end of this file
+    #[cfg(feature = "std")]
+    pub fn crc(&self) -> Result<u32> {
+        let mut buf = WriterWithCRC::new(io::sink(), CRCParams::CRC_32_JAMCRC);
+        self.write_content(&mut buf)?;
+        Ok(buf.crc())
+    }
 
-        // CRC is documented in the suffix section as a little endian 32bit unsigned integer
-        try!(buf.write_crc::<LittleEndian>());
+    /// Read a `DfuseFile`, checking its CRC
+    #[cfg(feature = "std")]
+    pub fn read_from<T: Read>(buf: &mut T) -> Result<DfuseFile> {
+        let (file, crc) = DfuseFile::read_with_crc(buf)?;
+        if !crc.is_valid() {
+            return Err(Error::new(ErrorKind::InvalidData, "CRC mismatch"));
+        }
 
-        try!(buf.flush());
+        Ok(file)
+    }
 
-        Ok(())
+    /// Read a `DfuseFile` without rejecting a bad CRC
+    #[cfg(feature = "std")]
+    pub fn read_with_crc<T: Read>(buf: &mut T) -> Result<(DfuseFile, CrcCheck)> {
+        DfuseFile::read_limited(buf, &ParseLimits::default())
+    }
+
+    /// Read a `DfuseFile` without rejecting a bad CRC, checking `limits`
+    ///
+    /// Errors about a limit are `CodecError::LimitExceeded`, in an
+    /// `InvalidData` I/O error.
+    #[cfg(feature = "std")]
+    pub fn read_limited<T: Read>(buf: &mut T, limits: &ParseLimits) -> Result<(DfuseFile, CrcCheck)> {
+        // One byte more than allowed tells a file too large from a truncated one
+        let mut buf = Read::take(buf, (limits.max_size as u64).saturating_add(1));
+        let res = DfuseFile::read_content(&mut buf, limits);
+        if buf.limit() == 0 {
+            return Err(CodecError::LimitExceeded(codec::Limit::Size, limits.max_size).into());
+        }
+        res
+    }
+
+    #[cfg(feature = "std")]
+    fn read_content<T: Read>(buf: &mut T, limits: &ParseLimits) -> Result<(DfuseFile, CrcCheck)> {
+        let mut buf = ReaderWithCRC::new(buf);
+
+        let prefix = Prefix::read_from(&mut buf)?;
+        limits.check_targets(prefix.nb_images() as usize)?;
+
+        let mut images = Vec::new();
+        for _ in 0..prefix.nb_images() {
+            images.push(Image::read_limited(&mut buf, limits)?);
+        }
+
+        // Everything left is the suffix, which is at most 255 bytes
+        let mut tail = Vec::new();
+        (&mut buf).take(Suffix::MAX_LENGTH as u64 + 1).read_to_end(&mut tail)?;
+        if tail.len() > Suffix::MAX_LENGTH {
+            return Err(Error::new(ErrorKind::InvalidData, "trailing data after DFU suffix"));
+        }
+        if tail.len() < CRC_SIZE {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "truncated DFU suffix"));
+        }
+        let suffix = Suffix::read_from(&tail[..tail.len() - CRC_SIZE])?;
+
+        let (stored, computed) = match buf.crc::<LittleEndian>() {
+            Some(crc) => crc,
+            None => return Err(Error::new(ErrorKind::UnexpectedEof, "missing CRC")),
+        };
+
+        Ok((DfuseFile { images, suffix }, CrcCheck { stored, computed }))
     }
 }
 
@@ -121,6 +333,7 @@ impl DfuseFile {
 mod tests {
 
     use super::*;
+    use ::codec::Limit;
 
 
     #[test]
@@ -128,4 +341,122 @@ mod tests {
         let file: DfuseFile = DfuseFile::new();
         drop(file);
     }
+
+    fn written(file: &DfuseFile) -> Vec<u8> {
+        let mut buf = vec![];
+        file.write_to(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn can_read_what_was_written() {
+        let mut file = DfuseFile::new();
+        file.add_image("ST...", 0, 0x08000000, vec![0x01, 0x02, 0x03]);
+        file.add_unamed_image(1, 0x1FFFF800, vec![0xAA, 0x55]);
+        file.set_vendor_id(0x0483);
+        file.set_product_id(0xDF11);
+        file.suffix_mut().vendor_data = vec![0x01, 0x02, 0x03];
+
+        let buf = written(&file);
+        assert_eq!(buf.len(), file.size());
+        assert_eq!(DfuseFile::read_from(&mut &buf[..]).unwrap(), file);
+    }
+
+    #[test]
+    fn read_reject_bad_crc() {
+        let mut buf = written(&DfuseFile::new());
+        let last = buf.len() - 1;
+        buf[last] ^= 0xFF;
+
+        assert!(DfuseFile::read_from(&mut &buf[..]).is_err());
+
+        let (file, crc) = DfuseFile::read_with_crc(&mut &buf[..]).unwrap();
+        assert!(!crc.is_valid());
+        assert_eq!(crc.computed, file.crc().unwrap());
+        assert_eq!(crc.stored, crc.computed ^ 0xFF000000);
+        assert_eq!(DfuseFile::from_bytes(&buf).unwrap(), (file, crc));
+    }
+
+    #[test]
+    fn read_within_limits() {
+        let mut file = DfuseFile::new();
+        file.add_image("ST...", 0, 0x08000000, vec![0x01; 0x100]);
+        file.images_mut()[0].elements.push(ImageElement::new(0x08001000, vec![0x02; 0x10]));
+        file.add_unamed_image(1, 0x1FFFF800, vec![0xAA, 0x55]);
+        let buf = written(&file);
+
+        let limits = ParseLimits::new()
+            .max_size(buf.len())
+            .max_targets(2)
+            .max_elements(2)
+            .max_element_size(0x100);
+        assert_eq!(DfuseFile::from_bytes_limited(&buf, &limits).unwrap().0, file);
+        assert_eq!(DfuseFile::read_limited(&mut &buf[..], &limits).unwrap().0, file);
+
+        let exceeded = [(limits.max_size(buf.len() - 1), Limit::Size, buf.len() - 1),
+                        (limits.max_targets(1), Limit::Targets, 1),
+                        (limits.max_elements(1), Limit::Elements, 1),
+                        (limits.max_element_size(0xFF), Limit::ElementSize, 0xFF)];
+        for &(ref limits, limit, max) in &exceeded {
+            let err = CodecError::LimitExceeded(limit, max);
+            assert_eq!(DfuseFile::from_bytes_limited(&buf, limits).unwrap_err(), err);
+
+            let io_err = DfuseFile::read_limited(&mut &buf[..], limits).unwrap_err();
+            assert_eq!(io_err.kind(), ErrorKind::InvalidData);
+            assert_eq!(io_err.to_string(), err.to_string());
+        }
+        assert_eq!(CodecError::LimitExceeded(Limit::Elements, 1).to_string(),
+                   "target with more than 1 elements");
+    }
+
+    #[test]
+    fn reject_huge_counts() {
+        let mut file = DfuseFile::new();
+        file.add_image("ST...", 0, 0x08000000, vec![]);
+        let mut buf = written(&file);
+
+        // nb_elements claims 0xFFFFFFFF elements, the element its size
+        buf[11 + 270..11 + 274].copy_from_slice(&[0xFF; 4]);
+        assert_eq!(DfuseFile::from_bytes(&buf).unwrap_err(), CodecError::UnexpectedEnd);
+        assert!(DfuseFile::read_with_crc(&mut &buf[..]).is_err());
+
+        let mut buf = written(&file);
+        buf[11 + 274 + 4..11 + 274 + 8].copy_from_slice(&[0xFF; 4]);
+        assert_eq!(DfuseFile::from_bytes(&buf).unwrap_err(), CodecError::UnexpectedEnd);
+        assert!(DfuseFile::read_with_crc(&mut &buf[..]).is_err());
+    }
+
+    #[test]
+    fn bytes_match_stream() {
+        let mut file = DfuseFile::new();
+        file.add_image("ST...", 0, 0x08000000, vec![0x01, 0x02, 0x03]);
+        file.add_unamed_image(1, 0x1FFFF800, vec![0xAA, 0x55]);
+        file.suffix_mut().vendor_data = vec![0x01, 0x02, 0x03];
+
+        let buf = written(&file);
+        assert_eq!(file.to_bytes().unwrap(), buf);
+        let (read, crc) = DfuseFile::from_bytes(&buf).unwrap();
+        assert_eq!(read, file);
+        assert!(crc.is_valid());
+
+        for len in 0..buf.len() {
+            assert!(DfuseFile::from_bytes(&buf[..len]).is_err());
+        }
+
+        file.suffix_mut().vendor_data = vec![0; Suffix::MAX_LENGTH];
+        assert!(file.to_bytes().is_err());
+    }
+
+    #[test]
+    fn write_reject_too_many_targets() {
+        let mut file = DfuseFile::new();
+        for _ in 0..256 {
+            file.add_unamed_image(0, 0x08000000, vec![0x00]);
+        }
+
+        assert!(file.to_bytes().is_err());
+        let mut buf = Vec::new();
+        assert_eq!(file.write_to(&mut buf).unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert!(file.crc().is_err());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,333 @@
+// Copyright © 2016 - Samuel Dolt <samuel@dolt.ch>
+//
+// See the COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Flash memory layout and an in-memory flash model
+//!
+//! # Examples
+//!
+//! ```
+//! use dfuse::flash::FlashLayout;
+//!
+//! // STM32F405 internal flash
+//! let layout = FlashLayout::new(0x08000000)
+//!     .sectors(4, 16 * 1024)
+//!     .sectors(1, 64 * 1024)
+//!     .sectors(7, 128 * 1024);
+//!
+//! assert_eq!(layout.sector_of(0x08010000).unwrap().size, 64 * 1024);
+//! ```
+
+use ::elements::Image;
+use ::error::{Error, Result};
+use ::file::DfuseFile;
+
+/// Value of an erased flash byte
+pub const ERASED: u8 = 0xFF;
+
+/// An erase sector
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sector {
+    pub start: u32,
+    pub size: u32,
+}
+
+impl Sector {
+    /// Address of the first byte after this sector
+    pub fn end(&self) -> u64 {
+        self.start as u64 + self.size as u64
+    }
+
+    pub fn contains(&self, adress: u32) -> bool {
+        adress >= self.start && (adress as u64) < self.end()
+    }
+}
+
+/// Ordered list of erase sectors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlashLayout {
+    next: u64,
+    sectors: Vec<Sector>,
+}
+
+impl FlashLayout {
+    /// Create an empty layout, sectors will be added from `base`
+    pub fn new(base: u32) -> FlashLayout {
+        FlashLayout {
+            next: base as u64,
+            sectors: Vec::new(),
+        }
+    }
+
+    /// Create a layout of `count` sectors of `size` bytes
+    pub fn uniform(base: u32, count: u32, size: u32) -> FlashLayout {
+        FlashLayout::new(base).sectors(count, size)
+    }
+
+    /// Append `count` sectors of `size` bytes after the last sector
+    ///
+    /// Panics if a sector would start beyond the 32 bit address space.
+    pub fn sectors(self, count: u32, size: u32) -> FlashLayout {
+        self.try_sectors(count, size).expect("flash sector beyond the 32 bit address space")
+    }
+
+    /// Like `sectors`, `None` if a sector would start beyond the 32 bit
+    /// address space
+    pub fn try_sectors(mut self, count: u32, size: u32) -> Option<FlashLayout> {
+        for _ in 0..count {
+            if self.next > u32::MAX as u64 {
+                return None;
+            }
+            self.sectors.push(Sector {
+                start: self.next as u32,
+                size,
+            });
+            self.next += size as u64;
+        }
+        Some(self)
+    }
+
+    /// Continue the layout at `base`, for memories made of several ranges
+    pub fn at(mut self, base: u32) -> FlashLayout {
+        self.next = base as u64;
+        self
+    }
+
+    pub fn sector_list(&self) -> &[Sector] {
+        &self.sectors
+    }
+
+    /// Sector containing `adress`
+    pub fn sector_of(&self, adress: u32) -> Option<&Sector> {
+        self.sectors.iter().find(|s| s.contains(adress))
+    }
+
+    /// `true` if every byte from `start` to `end` (excluded) is in a sector
+    pub fn covers(&self, start: u32, end: u64) -> bool {
+        let mut adress = start as u64;
+        while adress < end {
+            if adress > u32::MAX as u64 {
+                return false;
+            }
+            match self.sector_of(adress as u32) {
+                Some(sector) => adress = sector.end(),
+                None => return false,
+            }
+        }
+        true
+    }
+
+    /// Total size in bytes
+    pub fn size(&self) -> u64 {
+        self.sectors.iter().fold(0, |sum, s| sum + s.size as u64)
+    }
+}
+
+/// Render the content of `image` between `start` and `start + size`
+///
+/// Bytes not covered by an element are left erased, later elements win
+/// when elements overlap.
+pub(crate) fn render(image: &Image, start: u32, size: u32) -> Vec<u8> {
+    let mut out = vec![ERASED; size as usize];
+    let end = start as u64 + size as u64;
+
+    for element in &image.elements {
+        let from = (element.start_adress as u64).max(start as u64);
+        let to = element.end_adress().min(end);
+        if from >= to {
+            continue;
+        }
+
+        let src = (from - element.start_adress as u64) as usize;
+        let dst = (from - start as u64) as usize;
+        let len = (to - from) as usize;
+        out[dst..dst + len].copy_from_slice(&element.data[src..src + len]);
+    }
+
+    out
+}
+
+/// An in-memory model of a NOR flash
+///
+/// Erasing set a whole sector to `0xFF`, programming can only clear bits
+/// like a real flash does.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlashMemory {
+    layout: FlashLayout,
+    sectors: Vec<Vec<u8>>,
+}
+
+impl FlashMemory {
+    /// Create a fully erased flash
+    pub fn new(layout: FlashLayout) -> FlashMemory {
+        let sectors = layout.sectors.iter().map(|s| vec![ERASED; s.size as usize]).collect();
+        FlashMemory { layout, sectors }
+    }
+
+    pub fn layout(&self) -> &FlashLayout {
+        &self.layout
+    }
+
+    fn index_of(&self, adress: u32) -> Result<usize> {
+        self.layout
+            .sectors
+            .iter()
+            .position(|s| s.contains(adress))
+            .ok_or(Error::Unmapped(adress))
+    }
+
+    /// Erase the sector containing `adress`
+    pub fn erase(&mut self, adress: u32) -> Result<()> {
+        let i = self.index_of(adress)?;
+        for b in &mut self.sectors[i] {
+            *b = ERASED;
+        }
+        Ok(())
+    }
+
+    /// Program `data` at `adress` without erasing
+    pub fn write(&mut self, adress: u32, data: &[u8]) -> Result<()> {
+        for (offset, b) in data.iter().enumerate() {
+            let adress = adress.wrapping_add(offset as u32);
+            let i = self.index_of(adress)?;
+            let pos = (adress - self.layout.sectors[i].start) as usize;
+            self.sectors[i][pos] &= *b;
+        }
+        Ok(())
+    }
+
+    /// Read `len` bytes from `adress`
+    pub fn read(&self, adress: u32, len: usize) -> Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(len);
+        for offset in 0..len {
+            let adress = adress.wrapping_add(offset as u32);
+            let i = self.index_of(adress)?;
+            out.push(self.sectors[i][(adress - self.layout.sectors[i].start) as usize]);
+        }
+        Ok(out)
+    }
+
+    /// Download an image like a `DfuSe` device does
+    ///
+    /// Every sector touched by an element is erased, then elements are
+    /// programmed in order.
+    pub fn download(&mut self, image: &Image) -> Result<()> {
+        for element in &image.elements {
+            if !self.layout.covers(element.start_adress, element.end_adress()) {
+                return Err(Error::Unmapped(element.start_adress));
+            }
+        }
+
+        let mut erased = vec![false; self.sectors.len()];
+        for element in &image.elements {
+            for (i, sector) in self.layout.sectors.iter().enumerate() {
+                let touched = (sector.start as u64) < element.end_adress() &&
+                              (element.start_adress as u64) < sector.end();
+                if touched && !erased[i] && !element.data.is_empty() {
+                    erased[i] = true;
+                    for b in &mut self.sectors[i] {
+                        *b = ERASED;
+                    }
+                }
+            }
+        }
+
+        for element in &image.elements {
+            self.write(element.start_adress, &element.data)?;
+        }
+
+        Ok(())
+    }
+
+    /// Download every image of `file` for `alternate`
+    pub fn download_file(&mut self, file: &DfuseFile, alternate: u8) -> Result<()> {
+        for image in file.images().iter().filter(|i| i.alternate == alternate) {
+            self.download(image)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::elements::{Image, ImageElement};
+
+    fn layout() -> FlashLayout {
+        FlashLayout::uniform(0x08000000, 4, 0x400)
+    }
+
+    #[test]
+    fn layout_find_sectors() {
+        let layout = FlashLayout::new(0x08000000).sectors(2, 0x100).sectors(1, 0x400);
+
+        assert_eq!(layout.sector_of(0x08000100),
+                   Some(&Sector {
+                       start: 0x08000100,
+                       size: 0x100,
+                   }));
+        assert_eq!(layout.sector_of(0x08000200).unwrap().size, 0x400);
+        assert_eq!(layout.sector_of(0x08000600), None);
+        assert!(layout.covers(0x08000000, 0x08000600));
+        assert!(!layout.covers(0x08000000, 0x08000601));
+    }
+
+    #[test]
+    fn layout_end_of_address_space() {
+        let layout = FlashLayout::new(0x00000000).sectors(1, 0x1000).at(0xFFFFF000).sectors(1, 0x1000);
+        assert!(layout.covers(0xFFFFF000, 0x100000000));
+        assert!(!layout.covers(0xFFFFF000, 0x100000001));
+        assert!(!layout.covers(0x00000000, 0x200000000));
+    }
+
+    #[test]
+    fn layout_reject_sector_beyond_address_space() {
+        assert!(FlashLayout::new(0xFFFFF000).try_sectors(1, 0x1000).is_some());
+        assert!(FlashLayout::new(0xFFFFF000).try_sectors(2, 0x1000).is_none());
+    }
+
+    #[test]
+    fn write_only_clear_bits() {
+        let mut flash = FlashMemory::new(layout());
+        flash.write(0x08000000, &[0x0F]).unwrap();
+        flash.write(0x08000000, &[0xF1]).unwrap();
+        assert_eq!(flash.read(0x08000000, 2).unwrap(), vec![0x01, 0xFF]);
+
+        flash.erase(0x08000000).unwrap();
+        assert_eq!(flash.read(0x08000000, 1).unwrap(), vec![0xFF]);
+    }
+
+    #[test]
+    fn download_erase_touched_sectors() {
+        let mut flash = FlashMemory::new(layout());
+        flash.write(0x08000000, &[0x00; 0xC00]).unwrap();
+
+        let image = Image {
+            name: None,
+            alternate: 0,
+            elements: vec![ImageElement::new(0x080003FF, vec![0x12, 0x34])],
+        };
+        flash.download(&image).unwrap();
+
+        assert_eq!(flash.read(0x080003FE, 3).unwrap(), vec![0xFF, 0x12, 0x34]);
+        assert_eq!(flash.read(0x080007FF, 2).unwrap(), vec![0xFF, 0x00]);
+    }
+
+    #[test]
+    fn download_reject_unmapped() {
+        let mut flash = FlashMemory::new(layout());
+        let image = Image {
+            name: None,
+            alternate: 0,
+            elements: vec![ImageElement::new(0x08000FFF, vec![0x12, 0x34])],
+        };
+
+        assert!(flash.download(&image).is_err());
+    }
+}
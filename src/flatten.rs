@@ -0,0 +1,152 @@
+// Copyright © 2016 - Samuel Dolt <samuel@dolt.ch>
+//
+// See the COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::io::Write;
+
+use ::elements::{Image, ImageElement};
+use ::error::{Error, Result};
+
+impl Image {
+    /// First address and address after the last byte of this image
+    ///
+    /// Return `None` if the image hold no data.
+    pub fn bounds(&self) -> Option<(u32, u64)> {
+        let elements = self.elements.iter().filter(|e| !e.data.is_empty());
+
+        let start = elements.clone().map(|e| e.start_adress).min();
+        let end = elements.map(|e| e.end_adress()).max();
+
+        match (start, end) {
+            (Some(start), Some(end)) => Some((start, end)),
+            _ => None,
+        }
+    }
+
+    /// Render the data from `base` to `end` (excluded) as a contiguous
+    /// binary, bytes not covered by an element are set to `fill`
+    ///
+    /// Data outside of the range is ignored. Fail with `Error::Overlap` if
+    /// two elements write the same address.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dfuse::{Image, ImageElement};
+    ///
+    /// let image = Image {
+    ///     name: None,
+    ///     alternate: 0,
+    ///     elements: vec![ImageElement::new(0x08000000, vec![0x01, 0x02]),
+    ///                    ImageElement::new(0x08000003, vec![0x04])],
+    /// };
+    ///
+    /// let flat = image.flatten(0x08000000, 0x08000005, 0xFF).unwrap();
+    /// assert_eq!(flat, vec![0x01, 0x02, 0xFF, 0x04, 0xFF]);
+    /// ```
+    pub fn flatten(&self, base: u32, end: u64, fill: u8) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        self.write_flat_to(&mut out, base, end, fill)?;
+        Ok(out)
+    }
+
+    /// Same as `flatten`, but write the binary to `buf`
+    pub fn write_flat_to<T: Write>(&self, buf: &mut T, base: u32, end: u64, fill: u8) -> Result<()> {
+        if end < base as u64 {
+            return Err(Error::InvalidRange {
+                start: base,
+                end,
+            });
+        }
+
+        if let Some(adress) = self.find_overlap() {
+            return Err(Error::Overlap {
+                alternate: self.alternate,
+                adress,
+            });
+        }
+
+        let mut elements: Vec<&ImageElement> = self.elements.iter().collect();
+        elements.sort_by_key(|e| e.start_adress);
+
+        let mut adress = base as u64;
+        for element in elements {
+            let from = (element.start_adress as u64).max(adress);
+            let to = element.end_adress().min(end);
+            if from >= to {
+                continue;
+            }
+
+            write_fill(buf, from - adress, fill)?;
+
+            let offset = (from - element.start_adress as u64) as usize;
+            buf.write_all(&element.data[offset..offset + (to - from) as usize])?;
+            adress = to;
+        }
+
+        write_fill(buf, end - adress, fill)?;
+        Ok(())
+    }
+}
+
+fn write_fill<T: Write>(buf: &mut T, len: u64, fill: u8) -> Result<()> {
+    let chunk = [fill; 256];
+    let mut left = len;
+    while left > 0 {
+        let n = left.min(chunk.len() as u64) as usize;
+        buf.write_all(&chunk[..n])?;
+        left -= n as u64;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ::elements::{Image, ImageElement};
+
+    fn image() -> Image {
+        Image {
+            name: None,
+            alternate: 0,
+            elements: vec![ImageElement::new(0x110, vec![0x11; 0x10]),
+                           ImageElement::new(0x100, vec![0x22; 0x08]),
+                           ImageElement::new(0x140, vec![])],
+        }
+    }
+
+    #[test]
+    fn bounds_ignore_empty_elements() {
+        assert_eq!(image().bounds(), Some((0x100, 0x120)));
+        assert_eq!(Image {
+                       name: None,
+                       alternate: 0,
+                       elements: vec![],
+                   }
+                   .bounds(),
+                   None);
+    }
+
+    #[test]
+    fn flatten_fill_gaps_and_clip() {
+        let flat = image().flatten(0x104, 0x124, 0x00).unwrap();
+
+        let mut expected = vec![0x22; 4];
+        expected.extend_from_slice(&[0x00; 8]);
+        expected.extend_from_slice(&[0x11; 0x10]);
+        expected.extend_from_slice(&[0x00; 4]);
+        assert_eq!(flat, expected);
+    }
+
+    #[test]
+    fn flatten_reject_overlap() {
+        let mut img = image();
+        img.elements.push(ImageElement::new(0x11F, vec![0x33; 2]));
+        assert!(img.flatten(0x100, 0x200, 0xFF).is_err());
+    }
+}
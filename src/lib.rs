@@ -27,6 +27,15 @@
 //!
 //! - `DfuSe` File Format Specification Rev 1: [link](http://rc.fdr.hu/UM0391.pdf)
 //! - USB Device Class Specification for DFU V 1.1: [link](http://www.usb.org/developers/docs/devclass_docs/DFU_1.1.pdf)
+//!
+//! # Features
+//!
+//! - `std` (default): reading and writing through `std::io`, and every
+//!   module built on it. Without it, the crate is `no_std` and needs
+//!   `alloc`: see the `codec` module.
+//! - `signing`: signature of files with Ed25519 or ECDSA P-256.
+//! - `encryption`: AES encryption of the elements.
+//! - `async`: writing and reading over `futures` `AsyncWrite` and `AsyncRead`.
 
 // #![deny(missing_docs)]
 // #![deny(warnings)]
@@ -34,15 +43,118 @@
 // #![cfg(feature="nightly")]
 // #![feature(test)]
 
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+#[cfg(any(feature = "std", test))]
+extern crate alloc;
+#[cfg(any(feature = "std", test))]
+extern crate core;
+#[cfg(not(any(feature = "std", test)))]
+#[macro_use]
+extern crate alloc;
+
 extern crate byteorder;
 extern crate crc;
+#[cfg(feature = "std")]
+extern crate sha1;
+#[cfg(feature = "std")]
+extern crate sha2;
+
+#[cfg(feature = "signing")]
+extern crate ed25519_dalek;
+#[cfg(feature = "signing")]
+extern crate p256;
+
+#[cfg(feature = "encryption")]
+extern crate aes;
+#[cfg(feature = "encryption")]
+extern crate cbc;
+#[cfg(feature = "encryption")]
+extern crate ctr;
+
+#[cfg(feature = "async")]
+extern crate futures;
 
 mod file;
-pub use file::DfuseFile;
+pub use file::{CrcCheck, DfuseFile};
 
 mod tools;
 
 mod elements;
+pub use elements::{Image, ImageElement, Prefix, Suffix, TargetPrefix};
+
+pub mod codec;
+pub use codec::ParseLimits;
+
+mod version;
+pub use version::{BcdVersion, ParseVersionError};
+
+#[cfg(feature = "std")]
+mod builder;
+#[cfg(feature = "std")]
+pub use builder::DfuseFileBuilder;
+
+#[cfg(feature = "std")]
+mod error;
+#[cfg(feature = "std")]
+pub use error::Error;
+
+#[cfg(feature = "std")]
+pub mod diff;
+
+#[cfg(feature = "std")]
+pub mod report;
+
+#[cfg(feature = "std")]
+pub mod digest;
+
+#[cfg(feature = "std")]
+pub mod provisioning;
+
+#[cfg(feature = "std")]
+pub mod flash;
+
+#[cfg(feature = "std")]
+mod delta;
+
+#[cfg(feature = "std")]
+mod merge;
+#[cfg(feature = "std")]
+pub use merge::MergePolicy;
+
+#[cfg(feature = "std")]
+mod optimize;
+#[cfg(feature = "std")]
+pub use optimize::OptimizeOptions;
+
+#[cfg(feature = "std")]
+mod flatten;
+
+#[cfg(feature = "std")]
+mod memory;
+
+#[cfg(feature = "std")]
+pub mod checksum;
+#[cfg(feature = "std")]
+pub use checksum::{ChecksumPatch, CrcAlgorithm};
+
+#[cfg(feature = "std")]
+pub mod metadata;
+
+#[cfg(feature = "std")]
+pub mod option_bytes;
+
+#[cfg(feature = "std")]
+pub mod device;
+
+#[cfg(feature = "signing")]
+pub mod signing;
+
+#[cfg(feature = "encryption")]
+pub mod encryption;
+
+#[cfg(feature = "async")]
+pub mod async_io;
 
 
 #[cfg(test)]
@@ -0,0 +1,173 @@
+// Copyright © 2016 - Samuel Dolt <samuel@dolt.ch>
+//
+// See the COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use ::error::{Error, Result};
+use ::file::DfuseFile;
+
+/// Check that `len` bytes from `adress` fit in the 32 bit address space
+fn range(adress: u32, len: usize) -> Result<u64> {
+    let end = adress as u64 + len as u64;
+    if end > 0x100000000 {
+        return Err(Error::InvalidRange {
+            start: adress,
+            end,
+        });
+    }
+    Ok(end)
+}
+
+impl DfuseFile {
+    /// Read `len` bytes from `adress` of the memory behind `alternate`
+    ///
+    /// Every target using `alternate` is looked at, later elements win when
+    /// elements overlap. Fail with `Error::Unmapped` on the first byte not
+    /// written by the file.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dfuse::DfuseFile;
+    ///
+    /// let mut file = DfuseFile::new();
+    /// file.add_image("Internal Flash", 0, 0x08000000, vec![0x00; 0x100]);
+    ///
+    /// file.write(0, 0x080000FE, &[0x12, 0x34, 0x56, 0x78]).unwrap();
+    /// assert_eq!(file.read(0, 0x080000FC, 6).unwrap(), vec![0x00, 0x00, 0x12, 0x34, 0x56, 0x78]);
+    /// assert_eq!(file.images()[0].elements[0].data.len(), 0x102);
+    /// ```
+    pub fn read(&self, alternate: u8, adress: u32, len: usize) -> Result<Vec<u8>> {
+        let end = range(adress, len)?;
+        let mut out = vec![0; len];
+        let mut covered = vec![false; len];
+
+        let elements = self.images()
+            .iter()
+            .filter(|i| i.alternate == alternate)
+            .flat_map(|i| i.elements.iter());
+        for element in elements {
+            let from = (element.start_adress as u64).max(adress as u64);
+            let to = element.end_adress().min(end);
+            if from >= to {
+                continue;
+            }
+
+            let dst = (from - adress as u64) as usize..(to - adress as u64) as usize;
+            let src = (from - element.start_adress as u64) as usize..(to - element.start_adress as u64) as usize;
+            out[dst.clone()].copy_from_slice(&element.data[src]);
+            for c in &mut covered[dst] {
+                *c = true;
+            }
+        }
+
+        match covered.iter().position(|&c| !c) {
+            Some(n) => Err(Error::Unmapped(adress + n as u32)),
+            None => Ok(out),
+        }
+    }
+
+    /// Write `data` at `adress` of the memory behind `alternate`
+    ///
+    /// Elements covering the range are patched, in every target using
+    /// `alternate`. Bytes not covered yet extend the element ending just before
+    /// them, or go in a new element of the first target using `alternate`.
+    /// Fail with `Error::UnknownAlternate` if no target uses `alternate`.
+    pub fn write(&mut self, alternate: u8, adress: u32, data: &[u8]) -> Result<()> {
+        range(adress, data.len())?;
+
+        let mut images = self.images_mut().iter_mut().filter(|i| i.alternate == alternate);
+        let first = match images.next() {
+            Some(image) => image,
+            None => return Err(Error::UnknownAlternate(alternate)),
+        };
+
+        // Patch the other targets first, so only what they don't cover
+        // ends up in the first one
+        let mut covered = vec![false; data.len()];
+        let end = adress as u64 + data.len() as u64;
+        for image in images {
+            for element in &mut image.elements {
+                let from = (element.start_adress as u64).max(adress as u64);
+                let to = element.end_adress().min(end);
+                if from >= to {
+                    continue;
+                }
+
+                let src = (from - adress as u64) as usize..(to - adress as u64) as usize;
+                let dst = (from - element.start_adress as u64) as usize..(to - element.start_adress as u64) as usize;
+                element.data[dst].copy_from_slice(&data[src.clone()]);
+                for c in &mut covered[src] {
+                    *c = true;
+                }
+            }
+        }
+
+        // Runs not covered by the other targets go to the first one
+        let mut i = 0;
+        while i < data.len() {
+            if covered[i] {
+                i += 1;
+                continue;
+            }
+            let j = covered[i..].iter().position(|&c| c).map_or(data.len(), |n| i + n);
+            first.patch_and_join(adress + i as u32, &data[i..j]);
+            i = j;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::elements::ImageElement;
+
+    fn file() -> DfuseFile {
+        let mut file = DfuseFile::new();
+        file.add_image("Internal Flash", 0, 0x08000000, vec![0x00; 0x10]);
+        file.images_mut()[0].elements.push(ImageElement::new(0x08000020, vec![0x11; 0x10]));
+        file.add_image("Internal Flash", 0, 0x08000100, vec![0x22; 0x10]);
+        file.add_image("Option Bytes", 1, 0x1FFFF800, vec![0xAA, 0x55]);
+        file
+    }
+
+    #[test]
+    fn read_across_elements() {
+        let file = file();
+        assert_eq!(file.read(0, 0x0800000E, 2).unwrap(), vec![0x00, 0x00]);
+        assert_eq!(file.read(0, 0x080000FF, 2).unwrap_err().to_string(),
+                   Error::Unmapped(0x080000FF).to_string());
+        assert_eq!(file.read(0, 0x08000104, 4).unwrap(), vec![0x22; 4]);
+        assert_eq!(file.read(1, 0x1FFFF800, 0).unwrap(), vec![]);
+        assert!(file.read(0, 0xFFFFFFFF, 2).is_err());
+    }
+
+    #[test]
+    fn write_fill_gap() {
+        let mut file = file();
+        file.write(0, 0x0800000C, &[0x33; 0x18]).unwrap();
+
+        let image = &file.images()[0];
+        assert_eq!(image.elements.len(), 1);
+        assert_eq!(image.elements[0].data.len(), 0x30);
+        assert_eq!(file.read(0, 0x0800000B, 0x1A).unwrap(),
+                   [&[0x00][..], &[0x33; 0x18][..], &[0x11]].concat());
+    }
+
+    #[test]
+    fn write_other_target() {
+        let mut file = file();
+        file.write(0, 0x080000FE, &[0x44; 4]).unwrap();
+
+        assert_eq!(file.images()[0].elements[2], ImageElement::new(0x080000FE, vec![0x44; 2]));
+        assert_eq!(&file.images()[1].elements[0].data[..4], &[0x44, 0x44, 0x22, 0x22]);
+        assert!(file.write(2, 0x00000000, &[0x00]).is_err());
+    }
+}
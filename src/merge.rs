@@ -0,0 +1,214 @@
+// Copyright © 2016 - Samuel Dolt <samuel@dolt.ch>
+//
+// See the COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use ::elements::Suffix;
+use ::error::{Error, Result};
+use ::file::DfuseFile;
+
+/// What to do when merged files have a target with the same alternate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergePolicy {
+    /// Fail with `Error::AlternateConflict`
+    Reject,
+    /// Append the elements to the first target with this alternate, fail
+    /// with `Error::AlternateConflict` if the targets have different names,
+    /// and with `Error::Overlap` if two elements write the same address
+    Combine,
+}
+
+impl DfuseFile {
+    /// Merge the targets of `other` into this file
+    ///
+    /// The suffix is replaced by `suffix` if given, the one of this file is
+    /// kept otherwise. Fail with `Error::InvalidFile` if the merged file would
+    /// hold more than 255 targets. On error, this file is left unchanged.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dfuse::{DfuseFile, MergePolicy};
+    ///
+    /// let mut bootloader = DfuseFile::new();
+    /// bootloader.add_image("Internal Flash", 0, 0x08000000, vec![0x00; 0x100]);
+    ///
+    /// let mut app = DfuseFile::new();
+    /// app.add_image("Internal Flash", 0, 0x08004000, vec![0x00; 0x100]);
+    ///
+    /// bootloader.merge(&app, MergePolicy::Combine, None).unwrap();
+    /// assert_eq!(bootloader.images()[0].elements.len(), 2);
+    /// ```
+    pub fn merge(&mut self, other: &DfuseFile, policy: MergePolicy, suffix: Option<Suffix>) -> Result<()> {
+        let mut images = self.images().to_vec();
+
+        for image in other.images() {
+            let existing = images.iter_mut().find(|i| i.alternate == image.alternate);
+
+            match (existing, policy) {
+                (None, _) => images.push(image.clone()),
+                (Some(_), MergePolicy::Reject) => {
+                    return Err(Error::AlternateConflict(image.alternate))
+                }
+                (Some(target), MergePolicy::Combine) => {
+                    if target.name != image.name {
+                        return Err(Error::AlternateConflict(image.alternate));
+                    }
+
+                    target.elements.extend(image.elements.iter().cloned());
+                    target.elements.sort_by_key(|e| e.start_adress);
+
+                    if let Some(adress) = target.find_overlap() {
+                        return Err(Error::Overlap {
+                            alternate: target.alternate,
+                            adress,
+                        });
+                    }
+                }
+            }
+        }
+
+        if images.len() > u8::MAX as usize {
+            return Err(Error::InvalidFile("more than 255 targets"));
+        }
+
+        *self.images_mut() = images;
+        if let Some(suffix) = suffix {
+            *self.suffix_mut() = suffix;
+        }
+        Ok(())
+    }
+
+    /// Split this file into one file per target
+    ///
+    /// Every file get a copy of `suffix` if given, of this file's suffix
+    /// otherwise.
+    pub fn split_by_target(&self, suffix: Option<&Suffix>) -> Vec<DfuseFile> {
+        let suffix = suffix.unwrap_or_else(|| self.suffix());
+        self.images()
+            .iter()
+            .map(|image| {
+                let mut file = DfuseFile::new();
+                *file.suffix_mut() = suffix.clone();
+                file.images_mut().push(image.clone());
+                file
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(alternate: u8, start: u32, len: usize) -> DfuseFile {
+        let mut file = DfuseFile::new();
+        file.add_image("ST...", alternate, start, vec![0x00; len]);
+        file
+    }
+
+    #[test]
+    fn merge_different_alternates() {
+        let mut a = file(0, 0x08000000, 0x10);
+        a.set_vendor_id(0x0483);
+        let b = file(1, 0x1FFFF800, 0x10);
+
+        a.merge(&b, MergePolicy::Reject, None).unwrap();
+        assert_eq!(a.images().len(), 2);
+        assert_eq!(a.suffix().usb_vid, 0x0483);
+    }
+
+    #[test]
+    fn merge_reject_same_alternate() {
+        let mut a = file(0, 0x08000000, 0x10);
+        let b = file(0, 0x08004000, 0x10);
+
+        match a.merge(&b, MergePolicy::Reject, None) {
+            Err(Error::AlternateConflict(0)) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+        assert_eq!(a, file(0, 0x08000000, 0x10));
+    }
+
+    #[test]
+    fn merge_reject_too_many_targets() {
+        let mut a = DfuseFile::new();
+        for alternate in 0..200 {
+            a.add_unamed_image(alternate, 0x08000000, vec![0x00]);
+        }
+        let mut b = DfuseFile::new();
+        for alternate in 200..=255 {
+            b.add_unamed_image(alternate, 0x08000000, vec![0x00]);
+        }
+        let before = a.clone();
+
+        match a.merge(&b, MergePolicy::Reject, None) {
+            Err(Error::InvalidFile(_)) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+        assert_eq!(a, before);
+    }
+
+    #[test]
+    fn merge_combine_detect_overlap() {
+        let mut a = file(0, 0x08000000, 0x10);
+
+        match a.merge(&file(0, 0x0800000F, 0x10), MergePolicy::Combine, None) {
+            Err(Error::Overlap { alternate: 0, adress: 0x0800000F }) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+        assert_eq!(a.images()[0].elements.len(), 1);
+
+        a.merge(&file(0, 0x08000010, 0x10), MergePolicy::Combine, None).unwrap();
+        assert_eq!(a.images()[0].elements.len(), 2);
+    }
+
+    #[test]
+    fn merge_combine_reject_other_name() {
+        let mut a = file(0, 0x08000000, 0x10);
+        let mut b = DfuseFile::new();
+        b.add_image("Other", 0, 0x08000010, vec![0x00; 0x10]);
+
+        match a.merge(&b, MergePolicy::Combine, None) {
+            Err(Error::AlternateConflict(0)) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+        assert_eq!(a, file(0, 0x08000000, 0x10));
+    }
+
+    #[test]
+    fn merge_and_split_with_given_suffix() {
+        let mut b = file(1, 0x1FFFF800, 0x10);
+        b.set_vendor_id(0x0483);
+        b.set_product_id(0xDF11);
+
+        let mut a = file(0, 0x08000000, 0x10);
+        a.merge(&b, MergePolicy::Reject, Some(b.suffix().clone())).unwrap();
+        assert_eq!(a.suffix(), b.suffix());
+
+        let suffix = Suffix::new();
+        for part in a.split_by_target(Some(&suffix)) {
+            assert_eq!(part.suffix(), &suffix);
+        }
+    }
+
+    #[test]
+    fn split_then_merge_give_same_file() {
+        let mut a = file(0, 0x08000000, 0x10);
+        a.merge(&file(1, 0x1FFFF800, 0x10), MergePolicy::Reject, None).unwrap();
+        a.set_product_id(0xDF11);
+
+        let parts = a.split_by_target(None);
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[1].suffix(), a.suffix());
+
+        let mut merged = parts[0].clone();
+        merged.merge(&parts[1], MergePolicy::Reject, None).unwrap();
+        assert_eq!(merged, a);
+    }
+}
@@ -0,0 +1,288 @@
+// Copyright © 2016 - Samuel Dolt <samuel@dolt.ch>
+//
+// See the COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Firmware metadata block
+//!
+//! The block is 48 bytes long, every field is little endian:
+//!
+//! | Offset | Size | Field                                       |
+//! |--------|------|---------------------------------------------|
+//! | 0      | 4    | Magic `FWMD`                                |
+//! | 4      | 2    | Block layout version, currently 1           |
+//! | 6      | 2    | Firmware version, BCD encoded               |
+//! | 8      | 8    | Build time, seconds since the Unix epoch    |
+//! | 16     | 20   | Git commit hash                             |
+//! | 36     | 4    | Application start address                   |
+//! | 40     | 4    | Application size                            |
+//! | 44     | 4    | CRC-32 of the application                   |
+//!
+//! The CRC is the usual CRC-32 (ISO-HDLC, as used by zlib) of the
+//! application region, bytes not covered by an element being `0xFF`.
+//!
+//! # Examples
+//!
+//! ```
+//! use dfuse::DfuseFile;
+//! use dfuse::metadata::MetadataBlock;
+//!
+//! let mut file = DfuseFile::new();
+//! file.add_image("Internal Flash", 0, 0x08000000, vec![0x00; 0x1000]);
+//! file.set_version(0x0220);
+//!
+//! let block = MetadataBlock::new(0x08000200, 0x08000400, 0xC00).build_time(1478736000);
+//! let metadata = file.inject_metadata(0, &block).unwrap();
+//!
+//! assert_eq!(metadata.version, 0x0220);
+//! ```
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use ::error::{Error, Result};
+use ::file::DfuseFile;
+use ::flash::ERASED;
+use ::tools::CRC;
+
+const MAGIC: [u8; 4] = [b'F', b'W', b'M', b'D'];
+const LAYOUT_VERSION: u16 = 1;
+
+/// Content of a metadata block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FirmwareMetadata {
+    pub version: u16,
+    pub build_time: u64,
+    pub git_hash: [u8; 20],
+    pub app_start: u32,
+    pub app_size: u32,
+    pub app_crc: u32,
+}
+
+impl FirmwareMetadata {
+    /// Size in bytes of an encoded block
+    pub const SIZE: usize = 48;
+
+    pub fn to_bytes(&self) -> [u8; 48] {
+        let mut buf = [0u8; 48];
+        buf[0..4].copy_from_slice(&MAGIC);
+        LittleEndian::write_u16(&mut buf[4..6], LAYOUT_VERSION);
+        LittleEndian::write_u16(&mut buf[6..8], self.version);
+        LittleEndian::write_u64(&mut buf[8..16], self.build_time);
+        buf[16..36].copy_from_slice(&self.git_hash);
+        LittleEndian::write_u32(&mut buf[36..40], self.app_start);
+        LittleEndian::write_u32(&mut buf[40..44], self.app_size);
+        LittleEndian::write_u32(&mut buf[44..48], self.app_crc);
+        buf
+    }
+
+    /// Decode a block, `None` if `buf` doesn't start with a valid block
+    pub fn from_bytes(buf: &[u8]) -> Option<FirmwareMetadata> {
+        if buf.len() < FirmwareMetadata::SIZE || buf[0..4] != MAGIC ||
+           LittleEndian::read_u16(&buf[4..6]) != LAYOUT_VERSION {
+            return None;
+        }
+
+        let mut git_hash = [0u8; 20];
+        git_hash.copy_from_slice(&buf[16..36]);
+
+        Some(FirmwareMetadata {
+            version: LittleEndian::read_u16(&buf[6..8]),
+            build_time: LittleEndian::read_u64(&buf[8..16]),
+            git_hash,
+            app_start: LittleEndian::read_u32(&buf[36..40]),
+            app_size: LittleEndian::read_u32(&buf[40..44]),
+            app_crc: LittleEndian::read_u32(&buf[44..48]),
+        })
+    }
+}
+
+/// Where to put a metadata block and what it should describe
+#[derive(Debug, Clone)]
+pub struct MetadataBlock {
+    adress: u32,
+    app_start: u32,
+    app_size: u32,
+    version: Option<u16>,
+    build_time: u64,
+    git_hash: [u8; 20],
+}
+
+impl MetadataBlock {
+    /// A block at `adress` for an application of `app_size` bytes at `app_start`
+    ///
+    /// The block can't be inside the application.
+    pub fn new(adress: u32, app_start: u32, app_size: u32) -> MetadataBlock {
+        MetadataBlock {
+            adress,
+            app_start,
+            app_size,
+            version: None,
+            build_time: 0,
+            git_hash: [0; 20],
+        }
+    }
+
+    /// Firmware version, the suffix firmware version by default
+    pub fn version(mut self, version: u16) -> MetadataBlock {
+        self.version = Some(version);
+        self
+    }
+
+    pub fn build_time(mut self, build_time: u64) -> MetadataBlock {
+        self.build_time = build_time;
+        self
+    }
+
+    pub fn git_hash(mut self, git_hash: [u8; 20]) -> MetadataBlock {
+        self.git_hash = git_hash;
+        self
+    }
+}
+
+impl DfuseFile {
+    /// Write a metadata block in the first target using `alternate`
+    ///
+    /// Elements covering the block are patched, the rest of the block extends
+    /// or joins the elements around it, or goes in a new element. Return the
+    /// metadata written.
+    pub fn inject_metadata(&mut self, alternate: u8, block: &MetadataBlock) -> Result<FirmwareMetadata> {
+        let block_end = block.adress as u64 + FirmwareMetadata::SIZE as u64;
+        let app_end = block.app_start as u64 + block.app_size as u64;
+        for &(start, end) in &[(block.adress, block_end), (block.app_start, app_end)] {
+            if end > 0x100000000 {
+                return Err(Error::InvalidRange { start, end });
+            }
+        }
+        if (block.adress as u64) < app_end && (block.app_start as u64) < block_end {
+            return Err(Error::Overlap {
+                alternate,
+                adress: block.adress.max(block.app_start),
+            });
+        }
+
+        let version = block.version.unwrap_or(self.suffix().fw_version);
+        let image = match self.images_mut().iter_mut().find(|i| i.alternate == alternate) {
+            Some(image) => image,
+            None => return Err(Error::Unmapped(block.adress)),
+        };
+
+        let mut crc = CRC::new_iso_hdlc();
+        for b in image.flatten(block.app_start, app_end, ERASED)? {
+            crc.add(b);
+        }
+
+        let metadata = FirmwareMetadata {
+            version,
+            build_time: block.build_time,
+            git_hash: block.git_hash,
+            app_start: block.app_start,
+            app_size: block.app_size,
+            app_crc: crc.finalize(),
+        };
+        image.patch_and_join(block.adress, &metadata.to_bytes());
+
+        Ok(metadata)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::elements::ImageElement;
+
+    fn file() -> DfuseFile {
+        let mut file = DfuseFile::new();
+        file.add_image("Internal Flash", 0, 0x08000000, (0..0x100).map(|i| i as u8).collect());
+        file.set_version(0x0102);
+        file
+    }
+
+    #[test]
+    fn metadata_encode_decode() {
+        let metadata = FirmwareMetadata {
+            version: 0x0220,
+            build_time: 0x0102030405060708,
+            git_hash: [0xAB; 20],
+            app_start: 0x08004000,
+            app_size: 0x1000,
+            app_crc: 0xCBF43926,
+        };
+        let bytes = metadata.to_bytes();
+
+        assert_eq!(&bytes[0..8], &[b'F', b'W', b'M', b'D', 0x01, 0x00, 0x20, 0x02]);
+        assert_eq!(&bytes[44..48], &[0x26, 0x39, 0xF4, 0xCB]);
+        assert_eq!(FirmwareMetadata::from_bytes(&bytes), Some(metadata));
+        assert_eq!(FirmwareMetadata::from_bytes(&bytes[1..]), None);
+    }
+
+    #[test]
+    fn inject_patch_existing_element() {
+        let mut file = file();
+        let block = MetadataBlock::new(0x08000010, 0x08000040, 0x100).git_hash([0x11; 20]);
+        let metadata = file.inject_metadata(0, &block).unwrap();
+
+        assert_eq!(metadata.version, 0x0102);
+        // zlib.crc32(bytes(range(0x40, 0x100)) + b"\xff" * 0x40)
+        assert_eq!(metadata.app_crc, 0x2CF1067A);
+
+        let image = &file.images()[0];
+        assert_eq!(image.elements.len(), 1);
+        assert_eq!(FirmwareMetadata::from_bytes(&image.elements[0].data[0x10..]), Some(metadata));
+    }
+
+    #[test]
+    fn inject_add_element() {
+        let mut file = file();
+        let block = MetadataBlock::new(0x08000200, 0x08000000, 0x100).version(0x0300);
+        let metadata = file.inject_metadata(0, &block).unwrap();
+
+        let image = &file.images()[0];
+        assert_eq!(image.elements[1], ImageElement::new(0x08000200, metadata.to_bytes().to_vec()));
+    }
+
+    #[test]
+    fn inject_over_element_end() {
+        let mut file = file();
+        let block = MetadataBlock::new(0x080000F0, 0x08000000, 0x80);
+        let metadata = file.inject_metadata(0, &block).unwrap();
+
+        let image = &file.images()[0];
+        assert_eq!(image.elements.len(), 1);
+        assert_eq!(image.elements[0].data.len(), 0x120);
+        assert_eq!(FirmwareMetadata::from_bytes(&image.elements[0].data[0xF0..]), Some(metadata));
+    }
+
+    #[test]
+    fn inject_across_contiguous_elements() {
+        let mut file = file();
+        file.images_mut()[0].elements.push(ImageElement::new(0x08000100, vec![0x00; 0x100]));
+        let block = MetadataBlock::new(0x080000F0, 0x08000000, 0x80);
+        let metadata = file.inject_metadata(0, &block).unwrap();
+
+        let image = &file.images()[0];
+        assert_eq!(image.elements.len(), 1);
+        assert_eq!(image.elements[0].data.len(), 0x200);
+        assert_eq!(FirmwareMetadata::from_bytes(&image.elements[0].data[0xF0..]), Some(metadata));
+    }
+
+    #[test]
+    fn inject_reject_invalid_block() {
+        let mut file = file();
+        assert!(file.inject_metadata(0, &MetadataBlock::new(0x08000000, 0x08000010, 0x10)).is_err());
+        assert!(file.inject_metadata(1, &MetadataBlock::new(0x08000000, 0x08000100, 0x10)).is_err());
+        match file.inject_metadata(0, &MetadataBlock::new(0xFFFFFFE0, 0x08000000, 0x10)) {
+            Err(Error::InvalidRange { start: 0xFFFFFFE0, end: 0x100000010 }) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+        match file.inject_metadata(0, &MetadataBlock::new(0x08000000, 0xFFFFFF00, 0x200)) {
+            Err(Error::InvalidRange { start: 0xFFFFFF00, .. }) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+        assert_eq!(file, self::file());
+    }
+}
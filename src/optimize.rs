@@ -0,0 +1,185 @@
+// Copyright © 2016 - Samuel Dolt <samuel@dolt.ch>
+//
+// See the COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use ::elements::{Image, ImageElement};
+use ::error::{Error, Result};
+use ::flash::{FlashLayout, ERASED};
+
+/// Options for `Image::optimize`
+///
+/// By default, only adjacent elements are merged.
+#[derive(Debug, Clone, Default)]
+pub struct OptimizeOptions<'a> {
+    max_gap: u32,
+    layout: Option<&'a FlashLayout>,
+}
+
+impl<'a> OptimizeOptions<'a> {
+    pub fn new() -> OptimizeOptions<'a> {
+        OptimizeOptions::default()
+    }
+
+    /// Fill gaps smaller than `max_gap` bytes with `0xFF`
+    pub fn fill_gaps(mut self, max_gap: u32) -> OptimizeOptions<'a> {
+        self.max_gap = max_gap;
+        self
+    }
+
+    /// Split elements at the sector boundaries of `layout`
+    ///
+    /// A gap holding a whole sector is never filled, as filling it would
+    /// erase that sector.
+    pub fn split_sectors(mut self, layout: &'a FlashLayout) -> OptimizeOptions<'a> {
+        self.layout = Some(layout);
+        self
+    }
+}
+
+impl Image {
+    /// Merge adjacent elements to reduce the file size and the number of
+    /// `DfuSe` Set Address commands
+    ///
+    /// Elements are sorted by address and empty elements are dropped.
+    /// Fail with `Error::Overlap` if two elements write the same address.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dfuse::{Image, ImageElement, OptimizeOptions};
+    ///
+    /// let mut image = Image {
+    ///     name: None,
+    ///     alternate: 0,
+    ///     elements: vec![ImageElement::new(0x08000000, vec![0x01; 4]),
+    ///                    ImageElement::new(0x08000004, vec![0x02; 4]),
+    ///                    ImageElement::new(0x0800000A, vec![0x03; 2])],
+    /// };
+    ///
+    /// image.optimize(&OptimizeOptions::new().fill_gaps(4)).unwrap();
+    /// assert_eq!(image.elements.len(), 1);
+    /// assert_eq!(image.elements[0].data[8..10], [0xFF, 0xFF]);
+    /// ```
+    pub fn optimize(&mut self, options: &OptimizeOptions) -> Result<()> {
+        if let Some(adress) = self.find_overlap() {
+            return Err(Error::Overlap {
+                alternate: self.alternate,
+                adress,
+            });
+        }
+
+        let mut elements: Vec<ImageElement> = self.elements
+            .drain(..)
+            .filter(|e| !e.data.is_empty())
+            .collect();
+        elements.sort_by_key(|e| e.start_adress);
+
+        let mut merged: Vec<ImageElement> = Vec::new();
+        for element in elements {
+            if let Some(last) = merged.last_mut() {
+                let gap = element.start_adress as u64 - last.end_adress();
+                if gap == 0 || (gap < options.max_gap as u64 && can_fill(options, last, &element)) {
+                    last.data.resize(last.data.len() + gap as usize, ERASED);
+                    last.data.extend_from_slice(&element.data);
+                    continue;
+                }
+            }
+            merged.push(element);
+        }
+
+        self.elements = match options.layout {
+            Some(layout) => merged.into_iter().flat_map(|e| split(e, layout)).collect(),
+            None => merged,
+        };
+
+        Ok(())
+    }
+}
+
+fn can_fill(options: &OptimizeOptions, before: &ImageElement, after: &ImageElement) -> bool {
+    match options.layout {
+        Some(layout) => {
+            !layout.sector_list()
+                .iter()
+                .any(|s| s.start as u64 >= before.end_adress() && s.end() <= after.start_adress as u64)
+        }
+        None => true,
+    }
+}
+
+fn split(element: ImageElement, layout: &FlashLayout) -> Vec<ImageElement> {
+    let mut parts = Vec::new();
+    let mut start = element.start_adress as u64;
+    let mut data = &element.data[..];
+
+    while !data.is_empty() {
+        let len = match layout.sector_of(start as u32) {
+            Some(sector) => ((sector.end() - start) as usize).min(data.len()),
+            None => data.len(),
+        };
+
+        parts.push(ImageElement::new(start as u32, data[..len].to_vec()));
+        start += len as u64;
+        data = &data[len..];
+    }
+
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(elements: Vec<(u32, usize)>) -> Image {
+        Image {
+            name: None,
+            alternate: 0,
+            elements: elements.into_iter()
+                .map(|(start, len)| ImageElement::new(start, vec![0; len]))
+                .collect(),
+        }
+    }
+
+    fn spans(image: &Image) -> Vec<(u32, usize)> {
+        image.elements.iter().map(|e| (e.start_adress, e.data.len())).collect()
+    }
+
+    #[test]
+    fn optimize_merge_adjacent_elements() {
+        let mut img = image(vec![(0x110, 0x10), (0x100, 0x10), (0x130, 0x10), (0x200, 0)]);
+        img.optimize(&OptimizeOptions::new()).unwrap();
+
+        assert_eq!(spans(&img), vec![(0x100, 0x20), (0x130, 0x10)]);
+    }
+
+    #[test]
+    fn optimize_fill_small_gaps() {
+        let mut img = image(vec![(0x100, 0x10), (0x120, 0x10), (0x150, 0x10)]);
+        img.optimize(&OptimizeOptions::new().fill_gaps(0x20)).unwrap();
+
+        assert_eq!(spans(&img), vec![(0x100, 0x30), (0x150, 0x10)]);
+        assert_eq!(img.elements[0].data[0x10..0x20], [0xFF; 0x10]);
+    }
+
+    #[test]
+    fn optimize_split_at_sectors() {
+        let flash = FlashLayout::uniform(0x100, 4, 0x40);
+        let mut img = image(vec![(0x120, 0x30), (0x150, 0x20), (0x1C0, 0x10)]);
+        img.optimize(&OptimizeOptions::new().fill_gaps(0x100).split_sectors(&flash)).unwrap();
+
+        assert_eq!(spans(&img), vec![(0x120, 0x20), (0x140, 0x30), (0x1C0, 0x10)]);
+    }
+
+    #[test]
+    fn optimize_reject_overlap() {
+        let mut img = image(vec![(0x100, 0x10), (0x10F, 0x10)]);
+        assert!(img.optimize(&OptimizeOptions::new()).is_err());
+        assert_eq!(img.elements.len(), 2);
+    }
+}
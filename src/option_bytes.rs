@@ -0,0 +1,510 @@
+// Copyright © 2016 - Samuel Dolt <samuel@dolt.ch>
+//
+// See the COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Typed option bytes of STM32 families
+//!
+//! Every model encodes to the option byte area as stored in flash, each value
+//! being followed by its complement, and decodes from it only if every
+//! complement matches. The ST bootloader exposes this area on alternate 1.
+//!
+//! | Family | Address      | Size | Layout                                    |
+//! |--------|--------------|------|-------------------------------------------|
+//! | F0, F1 | `0x1FFFF800` | 16   | bytes, each followed by its complement    |
+//! | F4     | `0x1FFFC000` | 16   | half-word and complement per 64 bits      |
+//! | L4     | `0x1FFF7800` | 40   | 32 bit words, each followed by complement |
+//! | G0     | `0x1FFF7800` | 56   | 32 bit words, each followed by complement |
+//!
+//! # Examples
+//!
+//! ```
+//! use dfuse::DfuseFile;
+//! use dfuse::option_bytes::{OptionBytes, Rdp, Stm32F0};
+//!
+//! let ob = Stm32F0 {
+//!     rdp: Rdp::Level1,
+//!     ..Stm32F0::default()
+//! };
+//!
+//! let mut file = DfuseFile::new();
+//! file.images_mut().push(ob.to_image());
+//!
+//! assert_eq!(Stm32F0::from_image(&file.images()[0]), Some(ob));
+//! ```
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use ::elements::{Image, ImageElement};
+use ::flash::ERASED;
+
+/// Alternate setting of the option bytes in the ST bootloader
+pub const ALTERNATE: u8 = 1;
+
+/// Readout protection level
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rdp {
+    Level0,
+    Level1,
+    /// Permanent, the device can't be unprotected anymore
+    Level2,
+}
+
+impl Rdp {
+    fn to_byte(self) -> u8 {
+        match self {
+            Rdp::Level0 => 0xAA,
+            Rdp::Level1 => 0xBB,
+            Rdp::Level2 => 0xCC,
+        }
+    }
+
+    fn from_byte(b: u8) -> Rdp {
+        match b {
+            0xAA => Rdp::Level0,
+            0xCC => Rdp::Level2,
+            _ => Rdp::Level1,
+        }
+    }
+}
+
+/// Option bytes of a family, stored from `ADRESS` for `SIZE` bytes
+pub trait OptionBytes: Sized {
+    const ADRESS: u32;
+    const SIZE: usize;
+
+    /// Content of the option byte area, `SIZE` bytes long
+    fn to_bytes(&self) -> Vec<u8>;
+
+    /// Decode the option byte area, `None` if a complement doesn't match
+    fn from_bytes(buf: &[u8]) -> Option<Self>;
+
+    /// An "Option Bytes" image on `ALTERNATE`
+    fn to_image(&self) -> Image {
+        Image {
+            name: Some("Option Bytes".to_string()),
+            alternate: ALTERNATE,
+            elements: vec![ImageElement::new(Self::ADRESS, self.to_bytes())],
+        }
+    }
+
+    /// Decode the option bytes written by `image`
+    fn from_image(image: &Image) -> Option<Self> {
+        let end = Self::ADRESS as u64 + Self::SIZE as u64;
+        image.flatten(Self::ADRESS, end, ERASED).ok().and_then(|buf| Self::from_bytes(&buf))
+    }
+}
+
+/// `[value, !value]` for every byte
+fn byte_pairs(values: &[u8]) -> Vec<u8> {
+    values.iter().flat_map(|&b| vec![b, !b]).collect()
+}
+
+fn from_byte_pairs(buf: &[u8], count: usize) -> Option<Vec<u8>> {
+    if buf.len() < 2 * count {
+        return None;
+    }
+    buf[..2 * count]
+        .chunks(2)
+        .map(|p| if p[0] == !p[1] { Some(p[0]) } else { None })
+        .collect()
+}
+
+/// Little endian `[value, !value]` for every word
+fn word_pairs(values: &[u32]) -> Vec<u8> {
+    let mut buf = vec![0; 8 * values.len()];
+    for (chunk, &w) in buf.chunks_mut(8).zip(values) {
+        LittleEndian::write_u32(&mut chunk[0..4], w);
+        LittleEndian::write_u32(&mut chunk[4..8], !w);
+    }
+    buf
+}
+
+fn from_word_pairs(buf: &[u8], count: usize) -> Option<Vec<u32>> {
+    if buf.len() < 8 * count {
+        return None;
+    }
+    buf[..8 * count]
+        .chunks(8)
+        .map(|c| {
+            let w = LittleEndian::read_u32(&c[0..4]);
+            if w == !LittleEndian::read_u32(&c[4..8]) { Some(w) } else { None }
+        })
+        .collect()
+}
+
+/// Write protected area, from page `start` to page `end` included
+fn wrp_area(area: Option<(u8, u8)>, mask: u32) -> u32 {
+    let (start, end) = area.unwrap_or((mask as u8, 0));
+    !(mask | mask << 16) | (start as u32 & mask) | (end as u32 & mask) << 16
+}
+
+fn from_wrp_area(w: u32, mask: u32) -> Option<(u8, u8)> {
+    let (start, end) = ((w & mask) as u8, (w >> 16 & mask) as u8);
+    if start > end { None } else { Some((start, end)) }
+}
+
+/// STM32F0 option bytes, RM0091
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stm32F0 {
+    pub rdp: Rdp,
+    /// USER byte, `nBOOT0`, `nBOOT1`, `nRST_STDBY`, ...
+    pub user: u8,
+    pub data0: u8,
+    pub data1: u8,
+    /// Write protected sectors, one bit per sector group
+    pub wrp: u32,
+}
+
+impl Default for Stm32F0 {
+    fn default() -> Stm32F0 {
+        Stm32F0 {
+            rdp: Rdp::Level0,
+            user: 0xFF,
+            data0: 0xFF,
+            data1: 0xFF,
+            wrp: 0,
+        }
+    }
+}
+
+impl OptionBytes for Stm32F0 {
+    const ADRESS: u32 = 0x1FFFF800;
+    const SIZE: usize = 16;
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut values = vec![self.rdp.to_byte(), self.user, self.data0, self.data1];
+        values.extend_from_slice(&(!self.wrp).to_le_bytes());
+        byte_pairs(&values)
+    }
+
+    fn from_bytes(buf: &[u8]) -> Option<Stm32F0> {
+        let v = from_byte_pairs(buf, 8)?;
+        Some(Stm32F0 {
+            rdp: Rdp::from_byte(v[0]),
+            user: v[1],
+            data0: v[2],
+            data1: v[3],
+            wrp: !LittleEndian::read_u32(&v[4..8]),
+        })
+    }
+}
+
+/// STM32F1 option bytes, RM0008
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stm32F1 {
+    /// F1 devices have a single protection level
+    pub read_protected: bool,
+    /// USER byte, `WDG_SW`, `nRST_STOP` and `nRST_STDBY`
+    pub user: u8,
+    pub data0: u8,
+    pub data1: u8,
+    /// Write protected pages, one bit per page group
+    pub wrp: u32,
+}
+
+impl Default for Stm32F1 {
+    fn default() -> Stm32F1 {
+        Stm32F1 {
+            read_protected: false,
+            user: 0xFF,
+            data0: 0xFF,
+            data1: 0xFF,
+            wrp: 0,
+        }
+    }
+}
+
+impl OptionBytes for Stm32F1 {
+    const ADRESS: u32 = 0x1FFFF800;
+    const SIZE: usize = 16;
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let rdp = if self.read_protected { 0x00 } else { 0xA5 };
+        let mut values = vec![rdp, self.user, self.data0, self.data1];
+        values.extend_from_slice(&(!self.wrp).to_le_bytes());
+        byte_pairs(&values)
+    }
+
+    fn from_bytes(buf: &[u8]) -> Option<Stm32F1> {
+        let v = from_byte_pairs(buf, 8)?;
+        Some(Stm32F1 {
+            read_protected: v[0] != 0xA5,
+            user: v[1],
+            data0: v[2],
+            data1: v[3],
+            wrp: !LittleEndian::read_u32(&v[4..8]),
+        })
+    }
+}
+
+/// STM32F4 option bytes, RM0090
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stm32F4 {
+    pub rdp: Rdp,
+    /// Brown out reset level, 0 (off) to 3
+    pub bor_level: u8,
+    /// `WDG_SW`, `nRST_STOP` and `nRST_STDBY`, bits 5 to 7 of the USER byte
+    pub user: u8,
+    /// Write protected sectors, one bit per sector
+    pub wrp: u16,
+}
+
+impl Default for Stm32F4 {
+    fn default() -> Stm32F4 {
+        Stm32F4 {
+            rdp: Rdp::Level0,
+            bor_level: 0,
+            user: 0xE0,
+            wrp: 0,
+        }
+    }
+}
+
+impl OptionBytes for Stm32F4 {
+    const ADRESS: u32 = 0x1FFFC000;
+    const SIZE: usize = 16;
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let user = (self.user & 0xF0) | (3 - self.bor_level.min(3)) << 2;
+        let nwrp = !self.wrp & 0x0FFF;
+
+        let mut buf = vec![ERASED; 16];
+        for (chunk, value) in buf.chunks_mut(8).zip(&[[user, self.rdp.to_byte()], nwrp.to_le_bytes()]) {
+            chunk[0..2].copy_from_slice(value);
+            chunk[2] = !value[0];
+            chunk[3] = !value[1];
+        }
+        buf
+    }
+
+    fn from_bytes(buf: &[u8]) -> Option<Stm32F4> {
+        if buf.len() < 16 || buf.chunks(8).take(2).any(|c| c[0] != !c[2] || c[1] != !c[3]) {
+            return None;
+        }
+
+        Some(Stm32F4 {
+            rdp: Rdp::from_byte(buf[1]),
+            bor_level: 3 - (buf[0] >> 2 & 0x03),
+            user: buf[0] & 0xF0,
+            wrp: !LittleEndian::read_u16(&buf[8..10]) & 0x0FFF,
+        })
+    }
+}
+
+/// STM32L4 option bytes of the first bank, RM0351
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stm32L4 {
+    pub rdp: Rdp,
+    /// Brown out reset level, 0 to 4
+    pub bor_level: u8,
+    /// User bits of `FLASH_OPTR`, bits 11 to 31
+    pub user: u32,
+    /// Raw `PCROP1_STRT` and `PCROP1_END` words
+    pub pcrop1: (u32, u32),
+    /// Write protected pages, first and last included
+    pub wrp1a: Option<(u8, u8)>,
+    pub wrp1b: Option<(u8, u8)>,
+}
+
+impl Default for Stm32L4 {
+    fn default() -> Stm32L4 {
+        Stm32L4 {
+            rdp: Rdp::Level0,
+            bor_level: 0,
+            user: 0xFFEFF800,
+            pcrop1: (0xFFFFFFFF, 0x00000000),
+            wrp1a: None,
+            wrp1b: None,
+        }
+    }
+}
+
+impl OptionBytes for Stm32L4 {
+    const ADRESS: u32 = 0x1FFF7800;
+    const SIZE: usize = 40;
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let optr = (self.user & 0xFFFFF800) | (self.bor_level as u32 & 0x07) << 8 |
+                   self.rdp.to_byte() as u32;
+        word_pairs(&[optr,
+                     self.pcrop1.0,
+                     self.pcrop1.1,
+                     wrp_area(self.wrp1a, 0xFF),
+                     wrp_area(self.wrp1b, 0xFF)])
+    }
+
+    fn from_bytes(buf: &[u8]) -> Option<Stm32L4> {
+        let w = from_word_pairs(buf, 5)?;
+        Some(Stm32L4 {
+            rdp: Rdp::from_byte(w[0] as u8),
+            bor_level: (w[0] >> 8 & 0x07) as u8,
+            user: w[0] & 0xFFFFF800,
+            pcrop1: (w[1], w[2]),
+            wrp1a: from_wrp_area(w[3], 0xFF),
+            wrp1b: from_wrp_area(w[4], 0xFF),
+        })
+    }
+}
+
+/// STM32G0 option bytes, RM0444
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stm32G0 {
+    pub rdp: Rdp,
+    pub bor_enable: bool,
+    /// Falling and rising brown out reset thresholds, 0 to 3
+    pub bor_falling: u8,
+    pub bor_rising: u8,
+    /// User bits of `FLASH_OPTR`, bits 13 to 31
+    pub user: u32,
+    /// Raw `PCROP1A_STRT` and `PCROP1A_END` words
+    pub pcrop1a: (u32, u32),
+    /// Write protected pages, first and last included
+    pub wrp1a: Option<(u8, u8)>,
+    pub wrp1b: Option<(u8, u8)>,
+    /// Raw `PCROP1B_STRT` and `PCROP1B_END` words
+    pub pcrop1b: (u32, u32),
+}
+
+impl Default for Stm32G0 {
+    fn default() -> Stm32G0 {
+        Stm32G0 {
+            rdp: Rdp::Level0,
+            bor_enable: false,
+            bor_falling: 3,
+            bor_rising: 3,
+            user: 0xFFFFE000,
+            pcrop1a: (0xFFFFFFFF, 0x00000000),
+            wrp1a: None,
+            wrp1b: None,
+            pcrop1b: (0xFFFFFFFF, 0x00000000),
+        }
+    }
+}
+
+impl OptionBytes for Stm32G0 {
+    const ADRESS: u32 = 0x1FFF7800;
+    const SIZE: usize = 56;
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let optr = (self.user & 0xFFFFE000) | (self.bor_rising as u32 & 0x03) << 11 |
+                   (self.bor_falling as u32 & 0x03) << 9 |
+                   (self.bor_enable as u32) << 8 | self.rdp.to_byte() as u32;
+        word_pairs(&[optr,
+                     self.pcrop1a.0,
+                     self.pcrop1a.1,
+                     wrp_area(self.wrp1a, 0x7F),
+                     wrp_area(self.wrp1b, 0x7F),
+                     self.pcrop1b.0,
+                     self.pcrop1b.1])
+    }
+
+    fn from_bytes(buf: &[u8]) -> Option<Stm32G0> {
+        let w = from_word_pairs(buf, 7)?;
+        Some(Stm32G0 {
+            rdp: Rdp::from_byte(w[0] as u8),
+            bor_enable: w[0] & 0x100 != 0,
+            bor_falling: (w[0] >> 9 & 0x03) as u8,
+            bor_rising: (w[0] >> 11 & 0x03) as u8,
+            user: w[0] & 0xFFFFE000,
+            pcrop1a: (w[1], w[2]),
+            wrp1a: from_wrp_area(w[3], 0x7F),
+            wrp1b: from_wrp_area(w[4], 0x7F),
+            pcrop1b: (w[5], w[6]),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<T: OptionBytes + PartialEq + ::std::fmt::Debug>(ob: T) {
+        let bytes = ob.to_bytes();
+        assert_eq!(bytes.len(), T::SIZE);
+        assert_eq!(T::from_image(&ob.to_image()), Some(ob));
+
+        let mut corrupted = bytes.clone();
+        corrupted[0] ^= 0x01;
+        assert_eq!(T::from_bytes(&corrupted), None);
+    }
+
+    #[test]
+    fn f0_default() {
+        assert_eq!(Stm32F0::default().to_bytes(),
+                   vec![0xAA, 0x55, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00,
+                        0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00]);
+        round_trip(Stm32F0 {
+            rdp: Rdp::Level2,
+            user: 0x7F,
+            data0: 0x12,
+            data1: 0x34,
+            wrp: 0x00000003,
+        });
+    }
+
+    #[test]
+    fn f1_read_protection() {
+        let ob = Stm32F1 {
+            read_protected: true,
+            ..Stm32F1::default()
+        };
+        assert_eq!(&Stm32F1::default().to_bytes()[0..2], &[0xA5, 0x5A]);
+        assert_eq!(&ob.to_bytes()[0..2], &[0x00, 0xFF]);
+        round_trip(ob);
+    }
+
+    #[test]
+    fn f4_layout() {
+        let ob = Stm32F4 {
+            bor_level: 1,
+            wrp: 0x0001,
+            ..Stm32F4::default()
+        };
+        assert_eq!(ob.to_bytes(),
+                   vec![0xE8, 0xAA, 0x17, 0x55, 0xFF, 0xFF, 0xFF, 0xFF,
+                        0xFE, 0x0F, 0x01, 0xF0, 0xFF, 0xFF, 0xFF, 0xFF]);
+        round_trip(ob);
+    }
+
+    #[test]
+    fn l4_layout() {
+        let bytes = Stm32L4::default().to_bytes();
+        assert_eq!(&bytes[0..8], &[0xAA, 0xF8, 0xEF, 0xFF, 0x55, 0x07, 0x10, 0x00]);
+        assert_eq!(&bytes[24..32], &[0xFF, 0xFF, 0x00, 0xFF, 0x00, 0x00, 0xFF, 0x00]);
+
+        round_trip(Stm32L4 {
+            rdp: Rdp::Level1,
+            bor_level: 4,
+            wrp1a: Some((0, 15)),
+            ..Stm32L4::default()
+        });
+    }
+
+    #[test]
+    fn g0_layout() {
+        let ob = Stm32G0 {
+            bor_enable: true,
+            bor_falling: 1,
+            bor_rising: 2,
+            wrp1b: Some((0x10, 0x1F)),
+            ..Stm32G0::default()
+        };
+        let bytes = ob.to_bytes();
+        assert_eq!(&bytes[0..4], &[0xAA, 0xF3, 0xFF, 0xFF]);
+        assert_eq!(&bytes[32..36], &[0x90, 0xFF, 0x9F, 0xFF]);
+        round_trip(ob);
+    }
+
+    #[test]
+    fn from_image_need_whole_area() {
+        let mut image = Stm32F0::default().to_image();
+        image.elements[0].data.truncate(8);
+        assert_eq!(Stm32F0::from_image(&image), None);
+    }
+}
@@ -0,0 +1,304 @@
+// Copyright © 2016 - Samuel Dolt <samuel@dolt.ch>
+//
+// See the COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Per unit files, built from a template
+//!
+//! Every unit gets its own values, a serial number, a MAC address, a key...
+//! written at the addresses of the fields, then checksums are computed
+//! again. Files are written in parallel, with a `manifest.csv` giving the
+//! file and its SHA-256 for every serial number.
+//!
+//! # Examples
+//!
+//! ```
+//! use dfuse::DfuseFile;
+//! use dfuse::provisioning::{Provisioning, Unit};
+//!
+//! let mut template = DfuseFile::new();
+//! template.add_image("Internal Flash", 0, 0x08000000, vec![0xFF; 0x100]);
+//!
+//! let provisioning = Provisioning::new(template).field(0, 0x080000F0);
+//!
+//! let unit = Unit::new("SN0001").value(b"SN0001".to_vec());
+//! let file = provisioning.build(&unit).unwrap();
+//! assert_eq!(file.read(0, 0x080000F0, 6).unwrap(), b"SN0001");
+//! ```
+
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use sha2::{Digest, Sha256};
+
+use ::checksum::ChecksumPatch;
+use ::digest::hex;
+use ::error::{Error, Result};
+use ::file::DfuseFile;
+
+/// Name of the manifest written with the files
+pub const MANIFEST: &str = "manifest.csv";
+
+/// Values of a unit, in the order of the fields
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unit {
+    pub serial: String,
+    pub values: Vec<Vec<u8>>,
+}
+
+impl Unit {
+    pub fn new(serial: &str) -> Unit {
+        Unit {
+            serial: serial.to_string(),
+            values: Vec::new(),
+        }
+    }
+
+    /// Value of the next field
+    pub fn value(mut self, value: Vec<u8>) -> Unit {
+        self.values.push(value);
+        self
+    }
+}
+
+/// A file written for a unit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub serial: String,
+    pub path: PathBuf,
+    /// SHA-256 of the file, lowercase hexadecimal
+    pub sha256: String,
+}
+
+/// Template and where the values of a unit go
+#[derive(Debug, Clone)]
+pub struct Provisioning {
+    template: DfuseFile,
+    fields: Vec<(u8, u32)>,
+    checksums: Vec<(u8, ChecksumPatch)>,
+    threads: usize,
+}
+
+impl Provisioning {
+    pub fn new(template: DfuseFile) -> Provisioning {
+        Provisioning {
+            template,
+            fields: Vec::new(),
+            checksums: Vec::new(),
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+
+    /// Add a field at `adress` of the memory behind `alternate`
+    pub fn field(mut self, alternate: u8, adress: u32) -> Provisioning {
+        self.fields.push((alternate, adress));
+        self
+    }
+
+    /// Compute a checksum once the values are written, in the first target
+    /// using `alternate`
+    pub fn checksum(mut self, alternate: u8, patch: ChecksumPatch) -> Provisioning {
+        self.checksums.push((alternate, patch));
+        self
+    }
+
+    /// Number of files written at the same time, at least one
+    pub fn threads(mut self, threads: usize) -> Provisioning {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// File of `unit`
+    ///
+    /// `unit` must have a value for every field, `build` failing with
+    /// `Error::InvalidUnit` otherwise.
+    pub fn build(&self, unit: &Unit) -> Result<DfuseFile> {
+        if unit.values.len() != self.fields.len() {
+            return Err(invalid(unit, "not one value per field"));
+        }
+
+        let mut file = self.template.clone();
+        for (&(alternate, adress), value) in self.fields.iter().zip(&unit.values) {
+            file.write(alternate, adress, value)?;
+        }
+
+        for &(alternate, ref patch) in &self.checksums {
+            let image = match file.images_mut().iter_mut().find(|i| i.alternate == alternate) {
+                Some(image) => image,
+                None => return Err(Error::UnknownAlternate(alternate)),
+            };
+            image.patch_checksum(patch)?;
+        }
+
+        Ok(file)
+    }
+
+    fn write_unit(&self, unit: &Unit, dir: &Path) -> Result<ManifestEntry> {
+        let mut buf = Vec::new();
+        self.build(unit)?.write_to(&mut buf)?;
+
+        let path = dir.join(format!("{}.dfu", unit.serial));
+        File::create(&path)?.write_all(&buf)?;
+
+        Ok(ManifestEntry {
+            serial: unit.serial.clone(),
+            path,
+            sha256: hex(&Sha256::digest(&buf)),
+        })
+    }
+
+    /// Write a file per unit in `dir`, named after the serial number, and
+    /// the manifest
+    ///
+    /// Serial numbers may only use ASCII letters, digits, `-`, `_` and `.`,
+    /// and must be unique, ignoring case. Nothing is written otherwise, and
+    /// `write_all` fails with `Error::InvalidUnit`. Return the manifest
+    /// entries, in the order of `units`.
+    pub fn write_all<I: IntoIterator<Item = Unit>>(&self, units: I, dir: &Path) -> Result<Vec<ManifestEntry>> {
+        let units: Vec<Unit> = units.into_iter().collect();
+
+        let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.';
+        let mut serials = HashSet::with_capacity(units.len());
+        for unit in &units {
+            if unit.serial.is_empty() || unit.serial.starts_with('.') || !unit.serial.chars().all(valid) {
+                return Err(invalid(unit, "serial can't be used as a file name"));
+            }
+            // File names may not be case sensitive
+            if !serials.insert(unit.serial.to_ascii_lowercase()) {
+                return Err(invalid(unit, "serial used by more than one unit"));
+            }
+        }
+
+        fs::create_dir_all(dir)?;
+
+        let next = AtomicUsize::new(0);
+        let results: Mutex<Vec<Option<Result<ManifestEntry>>>> =
+            Mutex::new(units.iter().map(|_| None).collect());
+
+        thread::scope(|scope| {
+            for _ in 0..self.threads.min(units.len()) {
+                scope.spawn(|| loop {
+                    let n = next.fetch_add(1, Ordering::Relaxed);
+                    if n >= units.len() {
+                        break;
+                    }
+                    let res = self.write_unit(&units[n], dir);
+                    let failed = res.is_err();
+                    results.lock().unwrap()[n] = Some(res);
+                    if failed {
+                        // Stop handing out units
+                        next.store(units.len(), Ordering::Relaxed);
+                        break;
+                    }
+                });
+            }
+        });
+
+        let mut entries = Vec::with_capacity(units.len());
+        for res in results.into_inner().unwrap().into_iter().flatten() {
+            entries.push(res?);
+        }
+
+        let mut manifest = File::create(dir.join(MANIFEST))?;
+        writeln!(manifest, "serial,file,sha256")?;
+        for entry in &entries {
+            let name = entry.path.file_name().map_or(String::new(), |n| n.to_string_lossy().into_owned());
+            writeln!(manifest, "{},{},{}", entry.serial, name, entry.sha256)?;
+        }
+
+        Ok(entries)
+    }
+}
+
+fn invalid(unit: &Unit, reason: &'static str) -> Error {
+    Error::InvalidUnit {
+        serial: unit.serial.clone(),
+        reason,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use ::checksum::CrcAlgorithm;
+
+    fn provisioning() -> Provisioning {
+        let mut template = DfuseFile::new();
+        template.add_image("Internal Flash", 0, 0x08000000, vec![0x00; 0x40]);
+
+        Provisioning::new(template)
+            .field(0, 0x08000040)
+            .field(0, 0x08000048)
+            .checksum(0, ChecksumPatch::new(0x08000000, 0x08000050, 0x08000050).algorithm(CrcAlgorithm::Stm32))
+            .threads(3)
+    }
+
+    fn unit(n: u32) -> Unit {
+        Unit::new(&format!("SN{:04}", n))
+            .value(n.to_le_bytes().to_vec())
+            .value(vec![0x02, 0x00, 0x00, 0x00, 0x00, n as u8, 0xFF, 0xFF])
+    }
+
+    #[test]
+    fn build_patch_values_and_crc() {
+        let provisioning = provisioning();
+        let file = provisioning.build(&unit(7)).unwrap();
+
+        assert_eq!(file.read(0, 0x08000040, 4).unwrap(), vec![7, 0, 0, 0]);
+        assert_eq!(file.read(0, 0x08000048, 8).unwrap(), vec![2, 0, 0, 0, 0, 7, 0xFF, 0xFF]);
+        assert!(file.read(0, 0x08000044, 4).is_err());
+
+        let flat = file.images()[0].flatten(0x08000000, 0x08000050, 0xFF).unwrap();
+        let mut crc = ::checksum::CRC::new(::checksum::CRCParams::STM32);
+        for word in flat.chunks(4) {
+            crc.add_u32(u32::from_le_bytes([word[0], word[1], word[2], word[3]]));
+        }
+        assert_eq!(file.read(0, 0x08000050, 4).unwrap(), crc.finalize().to_le_bytes());
+
+        assert!(provisioning.build(&Unit::new("SN")).is_err());
+    }
+
+    #[test]
+    fn write_files_and_manifest() {
+        let dir = env::temp_dir().join(format!("dfuse-provisioning-{}", ::std::process::id()));
+        let entries = provisioning().write_all((0..10).map(unit), &dir).unwrap();
+
+        assert_eq!(entries.len(), 10);
+        assert_eq!(entries[3].serial, "SN0003");
+        let buf = fs::read(&entries[3].path).unwrap();
+        assert_eq!(entries[3].sha256, hex(&Sha256::digest(&buf)));
+        assert_eq!(DfuseFile::read_from(&mut &buf[..]).unwrap().read(0, 0x08000040, 1).unwrap(), vec![3]);
+
+        let manifest = fs::read_to_string(dir.join(MANIFEST)).unwrap();
+        let lines: Vec<&str> = manifest.lines().collect();
+        assert_eq!(lines.len(), 11);
+        assert_eq!(lines[4], format!("SN0003,SN0003.dfu,{}", entries[3].sha256));
+
+        assert!(provisioning().write_all(vec![unit(1), Unit::new("../x")], &dir).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reject_duplicate_serials() {
+        let dir = env::temp_dir().join(format!("dfuse-provisioning-duplicate-{}", ::std::process::id()));
+        let mut twin = unit(2);
+        twin.serial = "sn0002".to_string();
+
+        match provisioning().write_all(vec![unit(1), unit(2), twin], &dir) {
+            Err(Error::InvalidUnit { ref serial, .. }) if serial == "sn0002" => {}
+            res => panic!("unexpected {:?}", res),
+        }
+        assert!(!dir.exists());
+    }
+}
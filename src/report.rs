@@ -0,0 +1,468 @@
+// Copyright © 2016 - Samuel Dolt <samuel@dolt.ch>
+//
+// See the COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Human readable description of a `DfuseFile`
+//!
+//! # Examples
+//!
+//! ```
+//! use dfuse::DfuseFile;
+//!
+//! let mut file = DfuseFile::new();
+//! file.add_image("Internal Flash", 0, 0x08000000, vec![0x00; 0x10]);
+//!
+//! let report = file.report().hexdump(8).to_string();
+//! assert!(report.contains("0x08000000..0x08000010"));
+//! assert!(report.contains("08000008  00 00 00 00 00 00 00 00"));
+//! ```
+//!
+//! The same report is available as JSON, with this schema:
+//!
+//! ```text
+//! {
+//!   "schema": 1,
+//!   "size": <file size in bytes>,
+//!   "targets": [
+//!     {
+//!       "alternate": <alternate setting>,
+//!       "name": <target name or null>,
+//!       "size": <target size in bytes, target prefix included>,
+//!       "elements": [
+//!         {
+//!           "start": <first address>,
+//!           "end": <address after the last byte>,
+//!           "size": <size in bytes>,
+//!           "sha256": <SHA-256 of the data, lowercase hex>
+//!         }
+//!       ]
+//!     }
+//!   ],
+//!   "suffix": {
+//!     "vendor_id": <idVendor>,
+//!     "product_id": <idProduct>,
+//!     "version": <bcdDevice>,
+//!     "dfu_version": <bcdDFU>,
+//!     "vendor_data": <vendor data, lowercase hex>
+//!   },
+//!   "crc": {
+//!     "stored": <CRC in the file, or null>,
+//!     "computed": <CRC of the content>,
+//!     "valid": <true if both match, null without a stored CRC>
+//!   }
+//! }
+//! ```
+//!
+//! When the CRC can't be computed, because the file can't be written, `crc`
+//! is `null` and followed by `"crc_error": <reason>`.
+//!
+//! Numbers are JSON integers, fields may be added in a later version of the
+//! same schema.
+
+use std::fmt;
+use std::fmt::Write;
+use std::io;
+
+use sha2::{Digest, Sha256};
+
+use ::digest::hex;
+use ::elements::{Prefix, Suffix};
+use ::file::{CrcCheck, DfuseFile};
+
+/// Report of a file, its layout, suffix and CRC
+pub struct Report<'a> {
+    file: &'a DfuseFile,
+    crc: Option<CrcCheck>,
+    hexdump: Option<usize>,
+}
+
+impl<'a> Report<'a> {
+    pub fn new(file: &'a DfuseFile) -> Report<'a> {
+        Report {
+            file,
+            crc: None,
+            hexdump: None,
+        }
+    }
+
+    /// CRC read with the file, checked against the computed CRC
+    ///
+    /// Only the computed CRC is shown otherwise.
+    pub fn crc(mut self, crc: CrcCheck) -> Report<'a> {
+        self.crc = Some(crc);
+        self
+    }
+
+    /// Dump element data, `width` bytes per line
+    pub fn hexdump(mut self, width: usize) -> Report<'a> {
+        self.hexdump = Some(width.max(1));
+        self
+    }
+
+    fn write_hexdump(&self, f: &mut fmt::Formatter, start: u32, data: &[u8], width: usize) -> fmt::Result {
+        for (n, line) in data.chunks(width).enumerate() {
+            write!(f, "      {:08X} ", start as u64 + (n * width) as u64)?;
+            for b in line {
+                write!(f, " {:02X}", b)?;
+            }
+            for _ in line.len()..width {
+                f.write_str("   ")?;
+            }
+
+            f.write_str("  |")?;
+            for &b in line {
+                let c = if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' };
+                write!(f, "{}", c)?;
+            }
+            f.write_str("|\n")?;
+        }
+        Ok(())
+    }
+}
+
+/// A JSON string
+fn json_string(txt: &str) -> String {
+    let mut out = String::with_capacity(txt.len() + 2);
+    out.push('"');
+    for c in txt.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+impl<'a> Report<'a> {
+    /// Stored CRC, if given, and computed CRC
+    ///
+    /// Fail if the CRC can't be computed, when the file can't be written
+    fn crc_values(&self) -> io::Result<(Option<u32>, u32)> {
+        match self.crc {
+            Some(crc) => Ok((Some(crc.stored), crc.computed)),
+            None => Ok((None, self.file.crc()?)),
+        }
+    }
+
+    /// The report as a JSON document, see the module documentation
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        // Writing to a String can't fail
+        let _ = self.write_json(&mut out);
+        out
+    }
+
+    fn write_json(&self, out: &mut String) -> fmt::Result {
+        let file = self.file;
+
+        writeln!(out, "{{")?;
+        writeln!(out, "  \"schema\": 1,")?;
+        writeln!(out, "  \"size\": {},", file.size())?;
+        write!(out, "  \"targets\": [")?;
+
+        for (i, image) in file.images().iter().enumerate() {
+            let name = image.name.as_ref().map_or("null".to_string(), |n| json_string(n));
+            write!(out, "{}\n    {{\n", if i == 0 { "" } else { "," })?;
+            writeln!(out, "      \"alternate\": {},", image.alternate)?;
+            writeln!(out, "      \"name\": {},", name)?;
+            writeln!(out, "      \"size\": {},", image.size())?;
+            write!(out, "      \"elements\": [")?;
+
+            for (j, element) in image.elements.iter().enumerate() {
+                write!(out, "{}\n        {{\n", if j == 0 { "" } else { "," })?;
+                writeln!(out, "          \"start\": {},", element.start_adress)?;
+                writeln!(out, "          \"end\": {},", element.end_adress())?;
+                writeln!(out, "          \"size\": {},", element.data.len())?;
+                writeln!(out, "          \"sha256\": \"{}\"", hex(&Sha256::digest(&element.data)))?;
+                write!(out, "        }}")?;
+            }
+
+            if !image.elements.is_empty() {
+                write!(out, "\n      ")?;
+            }
+            write!(out, "]\n    }}")?;
+        }
+
+        if !file.images().is_empty() {
+            write!(out, "\n  ")?;
+        }
+        writeln!(out, "],")?;
+
+        let suffix = file.suffix();
+        writeln!(out, "  \"suffix\": {{")?;
+        writeln!(out, "    \"vendor_id\": {},", suffix.usb_vid)?;
+        writeln!(out, "    \"product_id\": {},", suffix.usb_pid)?;
+        writeln!(out, "    \"version\": {},", suffix.fw_version)?;
+        writeln!(out, "    \"dfu_version\": {},", Suffix::DFU_VERSION)?;
+        writeln!(out, "    \"vendor_data\": \"{}\"", hex(&suffix.vendor_data))?;
+        writeln!(out, "  }},")?;
+
+        match self.crc_values() {
+            Ok((stored, computed)) => {
+                let (stored, valid) = match stored {
+                    Some(stored) => (stored.to_string(), (stored == computed).to_string()),
+                    None => ("null".to_string(), "null".to_string()),
+                };
+                writeln!(out, "  \"crc\": {{")?;
+                writeln!(out, "    \"stored\": {},", stored)?;
+                writeln!(out, "    \"computed\": {},", computed)?;
+                writeln!(out, "    \"valid\": {}", valid)?;
+                writeln!(out, "  }}")?;
+            }
+            Err(err) => {
+                writeln!(out, "  \"crc\": null,")?;
+                writeln!(out, "  \"crc_error\": {}", json_string(&err.to_string()))?;
+            }
+        }
+        writeln!(out, "}}")
+    }
+}
+
+impl<'a> fmt::Display for Report<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let file = self.file;
+        let images = file.images();
+
+        writeln!(f, "DfuSe file, {} bytes", file.size())?;
+        writeln!(f, "Prefix: {} bytes, {} targets", Prefix::size(), images.len())?;
+
+        for image in images {
+            let name = match image.name {
+                Some(ref name) => format!("{:?}", name),
+                None => "(unnamed)".to_string(),
+            };
+            writeln!(f,
+                     "Target {} {}, {} elements, 0x{:08X} bytes",
+                     image.alternate,
+                     name,
+                     image.elements.len(),
+                     image.size())?;
+
+            for element in &image.elements {
+                writeln!(f,
+                         "    Element 0x{:08X}..0x{:08X}, 0x{:08X} bytes",
+                         element.start_adress,
+                         element.end_adress(),
+                         element.data.len())?;
+
+                if let Some(width) = self.hexdump {
+                    self.write_hexdump(f, element.start_adress, &element.data, width)?;
+                }
+            }
+        }
+
+        let suffix = file.suffix();
+        let version = match suffix.bcd_version() {
+            Some(version) => format!(" ({})", version),
+            None => String::new(),
+        };
+        writeln!(f,
+                 "Suffix: VID 0x{:04X}, PID 0x{:04X}, version 0x{:04X}{}, bcdDFU 0x{:04X}, {} bytes",
+                 suffix.usb_vid,
+                 suffix.usb_pid,
+                 suffix.fw_version,
+                 version,
+                 Suffix::DFU_VERSION,
+                 suffix.size() + 4)?;
+
+        match self.crc_values() {
+            Ok((None, computed)) => writeln!(f, "CRC: 0x{:08X}", computed),
+            Ok((Some(stored), computed)) if stored == computed => {
+                writeln!(f, "CRC: 0x{:08X} (valid)", stored)
+            }
+            Ok((Some(stored), computed)) => {
+                writeln!(f,
+                         "CRC: 0x{:08X} (invalid, expected 0x{:08X})",
+                         stored,
+                         computed)
+            }
+            Err(err) => writeln!(f, "CRC: unavailable: {}", err),
+        }
+    }
+}
+
+impl DfuseFile {
+    /// Report of this file, to be displayed
+    pub fn report(&self) -> Report<'_> {
+        Report::new(self)
+    }
+}
+
+impl fmt::Display for DfuseFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Report::new(self).fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file() -> DfuseFile {
+        let mut file = DfuseFile::new();
+        file.add_image("Internal Flash", 0, 0x08000000, b"Hello, DfuSe!\x00\x01".to_vec());
+        file.add_unamed_image(1, 0x1FFFF800, vec![0xAA, 0x55]);
+        file.set_vendor_id(0x0483);
+        file.set_product_id(0xDF11);
+        file
+    }
+
+    #[test]
+    fn display_file() {
+        let file = file();
+        let crc = file.crc().unwrap();
+        let expected = format!("DfuSe file, {} bytes
+Prefix: 11 bytes, 2 targets
+Target 0 \"Internal Flash\", 1 elements, 0x00000129 bytes
+    Element 0x08000000..0x0800000F, 0x0000000F bytes
+Target 1 (unnamed), 1 elements, 0x0000011C bytes
+    Element 0x1FFFF800..0x1FFFF802, 0x00000002 bytes
+Suffix: VID 0x0483, PID 0xDF11, version 0xFFFF, bcdDFU 0x011A, 16 bytes
+CRC: 0x{:08X}
+",
+                               file.size(),
+                               crc);
+        assert_eq!(file.to_string(), expected);
+
+        let check = CrcCheck {
+            stored: crc,
+            computed: crc,
+        };
+        assert!(file.report().crc(check).to_string().ends_with(&format!("CRC: 0x{:08X} (valid)\n", crc)));
+    }
+
+    #[test]
+    fn display_bcd_version() {
+        let mut file = file();
+        file.set_version(0x0231);
+        assert!(file.to_string().contains("version 0x0231 (2.3.1), "));
+    }
+
+    #[test]
+    fn display_unavailable_crc() {
+        let mut file = file();
+        file.suffix_mut().vendor_data = vec![0x00; 300];
+
+        let report = file.to_string();
+        assert!(report.ends_with("CRC: unavailable: too much DFU suffix vendor data\n"),
+                "{}",
+                report);
+    }
+
+    #[test]
+    fn report_bad_crc_and_hexdump() {
+        let file = file();
+        let crc = CrcCheck {
+            stored: 0x12345678,
+            computed: 0x9ABCDEF0,
+        };
+        let report = file.report().crc(crc).hexdump(8).to_string();
+
+        assert!(report.contains("CRC: 0x12345678 (invalid, expected 0x9ABCDEF0)\n"));
+        assert!(report.contains("      08000000  48 65 6C 6C 6F 2C 20 44  |Hello, D|\n\
+                                 \u{20}     08000008  66 75 53 65 21 00 01     |fuSe!..|\n"));
+    }
+
+    #[test]
+    fn json_report() {
+        let mut file = DfuseFile::new();
+        file.add_image("Flash \"A\"", 0, 0x08000000, b"abc".to_vec());
+        file.add_unamed_image(1, 0x1FFFF800, vec![]);
+        file.suffix_mut().vendor_data = vec![0x01, 0xAB];
+        let crc = file.crc().unwrap();
+
+        let expected = format!(r#"{{
+  "schema": 1,
+  "size": {},
+  "targets": [
+    {{
+      "alternate": 0,
+      "name": "Flash \"A\"",
+      "size": 285,
+      "elements": [
+        {{
+          "start": 134217728,
+          "end": 134217731,
+          "size": 3,
+          "sha256": "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        }}
+      ]
+    }},
+    {{
+      "alternate": 1,
+      "name": null,
+      "size": 282,
+      "elements": [
+        {{
+          "start": 536868864,
+          "end": 536868864,
+          "size": 0,
+          "sha256": "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        }}
+      ]
+    }}
+  ],
+  "suffix": {{
+    "vendor_id": 65535,
+    "product_id": 65535,
+    "version": 65535,
+    "dfu_version": 282,
+    "vendor_data": "01ab"
+  }},
+  "crc": {{
+    "stored": null,
+    "computed": {},
+    "valid": null
+  }}
+}}
+"#,
+                               file.size(),
+                               crc);
+        assert_eq!(file.report().to_json(), expected);
+
+        let check = CrcCheck {
+            stored: 0x12345678,
+            computed: crc,
+        };
+        assert!(file.report().crc(check).to_json().ends_with(&format!(r#"  "crc": {{
+    "stored": 305419896,
+    "computed": {},
+    "valid": false
+  }}
+}}
+"#,
+                                                                      crc)));
+
+        let empty = DfuseFile::new().report().to_json();
+        assert!(empty.contains("\"targets\": [],\n"));
+    }
+
+    #[test]
+    fn json_report_unavailable_crc() {
+        let mut file = DfuseFile::new();
+        file.suffix_mut().vendor_data = vec![0x00; 300];
+
+        let json = file.report().to_json();
+        assert!(json.ends_with(&format!(r#"    "vendor_data": "{}"
+  }},
+  "crc": null,
+  "crc_error": "too much DFU suffix vendor data"
+}}
+"#,
+                                        "00".repeat(300))),
+                "{}",
+                json);
+    }
+}
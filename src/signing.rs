@@ -0,0 +1,367 @@
+// Copyright © 2016 - Samuel Dolt <samuel@dolt.ch>
+//
+// See the COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Signature of `DfuSe` files, available with the `signing` feature
+//!
+//! The signed bytes are the `DfuSe` file without its signature, as written
+//! by `DfuseFile::write_to`, minus the 4 bytes of the CRC. The signature is
+//! stored as 64 raw bytes, `R || S` for both Ed25519 and ECDSA P-256 with
+//! SHA-256.
+//!
+//! # Examples
+//!
+//! ```
+//! use dfuse::DfuseFile;
+//! use dfuse::signing::{SignaturePlacement, SigningKey};
+//!
+//! let mut file = DfuseFile::new();
+//! file.add_image("Internal Flash", 0, 0x08000000, vec![0x00; 0x100]);
+//!
+//! let key = SigningKey::ed25519_from_bytes(&[0x42; 32]);
+//! let placement = SignaturePlacement::Element { alternate: 0, adress: 0x0801FFC0 };
+//!
+//! file.sign(&key, placement).unwrap();
+//! file.verify(&key.verifying_key(), placement).unwrap();
+//! ```
+
+use ed25519_dalek;
+use p256;
+use p256::ecdsa::signature::{Signer, Verifier};
+
+use ::elements::{Image, ImageElement};
+use ::error::{Error, Result};
+use ::file::DfuseFile;
+
+/// Size of a stored signature
+pub const SIGNATURE_SIZE: usize = 64;
+
+/// Name of the target created to hold a signature element
+pub const SIGNATURE_TARGET: &str = "Signature";
+
+/// Where the signature is stored in the file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignaturePlacement {
+    /// An element at `adress` in the first target using `alternate`,
+    /// a target named `Signature` is created if there is none
+    Element { alternate: u8, adress: u32 },
+    /// The vendor data of the DFU suffix
+    VendorData,
+}
+
+/// A private key
+#[derive(Debug, Clone)]
+pub enum SigningKey {
+    Ed25519(ed25519_dalek::SigningKey),
+    EcdsaP256(p256::ecdsa::SigningKey),
+}
+
+/// A public key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyingKey {
+    Ed25519(ed25519_dalek::VerifyingKey),
+    EcdsaP256(p256::ecdsa::VerifyingKey),
+}
+
+impl SigningKey {
+    /// Ed25519 key from its 32 bytes secret
+    pub fn ed25519_from_bytes(secret: &[u8; 32]) -> SigningKey {
+        SigningKey::Ed25519(ed25519_dalek::SigningKey::from_bytes(secret))
+    }
+
+    /// ECDSA P-256 key from its 32 bytes big endian scalar
+    pub fn p256_from_bytes(secret: &[u8]) -> Result<SigningKey> {
+        p256::ecdsa::SigningKey::from_slice(secret)
+            .map(SigningKey::EcdsaP256)
+            .map_err(|_| Error::InvalidKey)
+    }
+
+    pub fn verifying_key(&self) -> VerifyingKey {
+        match *self {
+            SigningKey::Ed25519(ref key) => VerifyingKey::Ed25519(key.verifying_key()),
+            SigningKey::EcdsaP256(ref key) => VerifyingKey::EcdsaP256(*key.verifying_key()),
+        }
+    }
+
+    fn sign(&self, msg: &[u8]) -> Vec<u8> {
+        match *self {
+            SigningKey::Ed25519(ref key) => key.sign(msg).to_bytes().to_vec(),
+            SigningKey::EcdsaP256(ref key) => {
+                let signature: p256::ecdsa::Signature = key.sign(msg);
+                signature.to_bytes().to_vec()
+            }
+        }
+    }
+}
+
+impl VerifyingKey {
+    /// Ed25519 key from its 32 bytes compressed point
+    pub fn ed25519_from_bytes(public: &[u8; 32]) -> Result<VerifyingKey> {
+        ed25519_dalek::VerifyingKey::from_bytes(public)
+            .map(VerifyingKey::Ed25519)
+            .map_err(|_| Error::InvalidKey)
+    }
+
+    /// ECDSA P-256 key from its SEC1 encoded point
+    pub fn p256_from_sec1(public: &[u8]) -> Result<VerifyingKey> {
+        p256::ecdsa::VerifyingKey::from_sec1_bytes(public)
+            .map(VerifyingKey::EcdsaP256)
+            .map_err(|_| Error::InvalidKey)
+    }
+
+    fn verify(&self, msg: &[u8], signature: &[u8]) -> Result<()> {
+        let valid = match *self {
+            VerifyingKey::Ed25519(ref key) => {
+                ed25519_dalek::Signature::from_slice(signature)
+                    .map(|s| key.verify(msg, &s).is_ok())
+                    .unwrap_or(false)
+            }
+            VerifyingKey::EcdsaP256(ref key) => {
+                p256::ecdsa::Signature::from_slice(signature)
+                    .map(|s| key.verify(msg, &s).is_ok())
+                    .unwrap_or(false)
+            }
+        };
+
+        if valid { Ok(()) } else { Err(Error::BadSignature) }
+    }
+}
+
+impl DfuseFile {
+    /// Remove the signature stored at `placement`, returning it
+    ///
+    /// Only an element of `SIGNATURE_SIZE` bytes is taken for a signature. A
+    /// target left empty is removed.
+    pub fn remove_signature(&mut self, placement: SignaturePlacement) -> Option<Vec<u8>> {
+        match placement {
+            SignaturePlacement::VendorData => {
+                let data = ::std::mem::take(&mut self.suffix_mut().vendor_data);
+                if data.is_empty() { None } else { Some(data) }
+            }
+            SignaturePlacement::Element { alternate, adress } => {
+                let images = self.images_mut();
+                let i = images.iter().position(|i| i.alternate == alternate)?;
+                let j = images[i].elements
+                    .iter()
+                    .position(|e| e.start_adress == adress && e.data.len() == SIGNATURE_SIZE)?;
+
+                let element = images[i].elements.remove(j);
+                if images[i].elements.is_empty() {
+                    images.remove(i);
+                }
+                Some(element.data)
+            }
+        }
+    }
+
+    /// Bytes covered by the signature stored at `placement`
+    pub fn signed_bytes(&self, placement: SignaturePlacement) -> Result<Vec<u8>> {
+        let mut unsigned = self.clone();
+        unsigned.remove_signature(placement);
+
+        let mut buf = Vec::with_capacity(unsigned.size());
+        unsigned.write_to(&mut buf)?;
+        let len = buf.len() - 4;
+        buf.truncate(len);
+        Ok(buf)
+    }
+
+    /// Sign this file with `key`, replacing any signature at `placement`
+    ///
+    /// Fail with `Error::Overlap` if a signature element would overwrite
+    /// another element, leaving this file unchanged.
+    pub fn sign(&mut self, key: &SigningKey, placement: SignaturePlacement) -> Result<()> {
+        let mut signed = self.clone();
+        signed.remove_signature(placement);
+        let signature = key.sign(&signed.signed_bytes(placement)?);
+
+        match placement {
+            SignaturePlacement::VendorData => signed.suffix_mut().vendor_data = signature,
+            SignaturePlacement::Element { alternate, adress } => {
+                let element = ImageElement::new(adress, signature);
+                let images = signed.images_mut();
+
+                match images.iter_mut().find(|i| i.alternate == alternate) {
+                    Some(image) => {
+                        image.elements.push(element);
+                        if let Some(adress) = image.find_overlap() {
+                            return Err(Error::Overlap { alternate, adress });
+                        }
+                    }
+                    None => {
+                        images.push(Image {
+                            name: Some(SIGNATURE_TARGET.to_string()),
+                            alternate,
+                            elements: vec![element],
+                        })
+                    }
+                }
+            }
+        }
+
+        *self = signed;
+        Ok(())
+    }
+
+    /// Check the signature stored at `placement` with `key`
+    pub fn verify(&self, key: &VerifyingKey, placement: SignaturePlacement) -> Result<()> {
+        let signature = self.clone()
+            .remove_signature(placement)
+            .ok_or(Error::MissingSignature)?;
+
+        key.verify(&self.signed_bytes(placement)?, &signature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_hex(txt: &str) -> Vec<u8> {
+        (0..txt.len() / 2).map(|i| u8::from_str_radix(&txt[2 * i..2 * i + 2], 16).unwrap()).collect()
+    }
+
+    fn ed25519_key() -> SigningKey {
+        // RFC 8032, section 7.1, TEST 2
+        let mut secret = [0u8; 32];
+        secret.copy_from_slice(&from_hex("4ccd089b28ff96da9db6c346ec114e0f\
+                                          5b8a319f35aba624da8cf6ed4fb8a6fb"));
+        SigningKey::ed25519_from_bytes(&secret)
+    }
+
+    fn p256_key() -> SigningKey {
+        // RFC 6979, section A.2.5
+        SigningKey::p256_from_bytes(&from_hex("C9AFA9D845BA75166B5C215767B1D693\
+                                               4E50C3DB36E89B127B8A622B120F6721"))
+            .unwrap()
+    }
+
+    fn file() -> DfuseFile {
+        let mut file = DfuseFile::new();
+        file.add_image("Internal Flash", 0, 0x08000000, vec![0x5A; 0x40]);
+        file.set_vendor_id(0x0483);
+        file.set_product_id(0xDF11);
+        file
+    }
+
+    #[test]
+    fn ed25519_test_vector() {
+        let key = ed25519_key();
+        assert_eq!(key.verifying_key(),
+                   VerifyingKey::ed25519_from_bytes(&{
+                           let mut public = [0u8; 32];
+                           public.copy_from_slice(&from_hex("3d4017c3e843895a92b70aa74d1b7ebc\
+                                                             9c982ccf2ec4968cc0cd55f12af4660c"));
+                           public
+                       })
+                       .unwrap());
+        assert_eq!(key.sign(&[0x72]),
+                   from_hex("92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da\
+                             085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00"));
+    }
+
+    #[test]
+    fn p256_test_vector() {
+        let key = p256_key();
+        assert_eq!(key.verifying_key(),
+                   VerifyingKey::p256_from_sec1(&from_hex("04\
+                        60FED4BA255A9D31C961EB74C6356D68C049B8923B61FA6CE669622E60F29FB6\
+                        7903FE1008B8BC99A41AE9E95628BC64F2F1B20C2D7E9F5177A3C294D4462299"))
+                       .unwrap());
+        assert_eq!(key.sign(b"sample"),
+                   from_hex("EFD48B2AACB6A8FD1140DD9CD45E81D69D2C877B56AAF991C34D0EA84EAF3716\
+                             F7CB1C942D657C41D436C7A1B6E29F65F3E900DBB9AFF4064DC4AB2F843ACDA8"));
+    }
+
+    #[test]
+    fn sign_as_element() {
+        let placement = SignaturePlacement::Element {
+            alternate: 0,
+            adress: 0x08000040,
+        };
+        let mut signed = file();
+        signed.sign(&ed25519_key(), placement).unwrap();
+
+        let elements = &signed.images()[0].elements;
+        assert_eq!(elements.len(), 2);
+        assert_eq!(elements[1].start_adress, 0x08000040);
+        assert_eq!(elements[1].data,
+                   from_hex("ea642360a9c7c04e5b5856937183c44fb329f54ed567c9d9172acc309ed0ddc1\
+                             ca2571376f5bbd9863da5610ec52412cd1b340e4c80f77f006533e7bef10130f"));
+        signed.verify(&ed25519_key().verifying_key(), placement).unwrap();
+    }
+
+    #[test]
+    fn sign_reject_overlap() {
+        let mut firmware = DfuseFile::new();
+        firmware.add_image("Internal Flash", 0, 0x08000000, vec![0x5A; 0x100]);
+        let mut signed = firmware.clone();
+
+        for &adress in &[0x08000000, 0x08000080, 0x07FFFFF0] {
+            let placement = SignaturePlacement::Element { alternate: 0, adress };
+            match signed.sign(&ed25519_key(), placement) {
+                Err(Error::Overlap { alternate: 0, .. }) => {}
+                res => panic!("unexpected {:?}", res),
+            }
+            assert_eq!(signed, firmware);
+            assert_eq!(signed.remove_signature(placement), None);
+            assert_eq!(signed, firmware);
+        }
+    }
+
+    #[test]
+    fn sign_in_new_target() {
+        let placement = SignaturePlacement::Element {
+            alternate: 3,
+            adress: 0x00000000,
+        };
+        let mut signed = file();
+        signed.sign(&p256_key(), placement).unwrap();
+
+        assert_eq!(signed.images()[1].name, Some(SIGNATURE_TARGET.to_string()));
+        assert_eq!(signed.signed_bytes(placement).unwrap().len(), file().size() - 4);
+        signed.verify(&p256_key().verifying_key(), placement).unwrap();
+
+        signed.remove_signature(placement);
+        assert_eq!(signed, file());
+    }
+
+    #[test]
+    fn sign_as_vendor_data() {
+        let placement = SignaturePlacement::VendorData;
+        let mut signed = file();
+        signed.sign(&p256_key(), placement).unwrap();
+
+        let mut buf = vec![];
+        signed.write_to(&mut buf).unwrap();
+        let read = DfuseFile::read_from(&mut &buf[..]).unwrap();
+
+        assert_eq!(read.suffix().vendor_data.len(), SIGNATURE_SIZE);
+        read.verify(&p256_key().verifying_key(), placement).unwrap();
+    }
+
+    #[test]
+    fn verify_detect_changes() {
+        let placement = SignaturePlacement::VendorData;
+        let key = ed25519_key();
+
+        match file().verify(&key.verifying_key(), placement) {
+            Err(Error::MissingSignature) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+
+        let mut signed = file();
+        signed.sign(&key, placement).unwrap();
+        signed.set_version(0x0220);
+        match signed.verify(&key.verifying_key(), placement) {
+            Err(Error::BadSignature) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+    }
+}
@@ -20,56 +20,158 @@ fn swap8(in_v: u8) -> u8 {
     out_v
 }
 
-fn swap32(in_v: u32) -> u32 {
+fn swap(in_v: u32, width: u8) -> u32 {
     let mut out_v = 0u32;
-    for pos in 0..32 {
+    for pos in 0..width {
         let bit = (in_v & (1 << pos)) >> pos;
-        out_v |= bit << (31 - pos);
+        out_v |= bit << (width - 1 - pos);
     }
     out_v
 }
 
+/// Parameters of a CRC, as listed in the CRC RevEng catalogue
+///
+/// `poly`, `initial` and `xor_out` are `width` bits values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CRCParams {
+    pub width: u8,
+    pub poly: u32,
+    pub ref_in: bool,
+    pub ref_out: bool,
+    pub initial: u32,
+    pub xor_out: u32,
+}
+
+impl CRCParams {
+    /// CRC-32/ISO-HDLC, the usual CRC-32, as used by zlib or Ethernet
+    pub const CRC_32_ISO_HDLC: CRCParams = CRCParams::crc32(0x04C11DB7, true, 0xFFFFFFFF, 0xFFFFFFFF);
+    /// CRC-32/JAMCRC, used by the `DfuSe` file suffix
+    pub const CRC_32_JAMCRC: CRCParams = CRCParams::crc32(0x04C11DB7, true, 0xFFFFFFFF, 0);
+    /// CRC-32/MPEG-2
+    pub const CRC_32_MPEG_2: CRCParams = CRCParams::crc32(0x04C11DB7, false, 0xFFFFFFFF, 0);
+    /// CRC-32/BZIP2
+    pub const CRC_32_BZIP2: CRCParams = CRCParams::crc32(0x04C11DB7, false, 0xFFFFFFFF, 0xFFFFFFFF);
+    /// STM32 CRC unit with its reset configuration
+    ///
+    /// Same as CRC-32/MPEG-2, the unit being fed 32 bit words with `CRC::add_u32`.
+    pub const STM32: CRCParams = CRCParams::CRC_32_MPEG_2;
+    /// CRC-16/CCITT, also known as CRC-16/KERMIT
+    pub const CRC_16_CCITT: CRCParams = CRCParams {
+        width: 16,
+        poly: 0x1021,
+        ref_in: true,
+        ref_out: true,
+        initial: 0,
+        xor_out: 0,
+    };
+    /// CRC-16/CCITT-FALSE, also known as CRC-16/IBM-3740
+    pub const CRC_16_CCITT_FALSE: CRCParams = CRCParams {
+        width: 16,
+        poly: 0x1021,
+        ref_in: false,
+        ref_out: false,
+        initial: 0xFFFF,
+        xor_out: 0,
+    };
+
+    const fn crc32(poly: u32, reflected: bool, initial: u32, xor_out: u32) -> CRCParams {
+        CRCParams {
+            width: 32,
+            poly,
+            ref_in: reflected,
+            ref_out: reflected,
+            initial,
+            xor_out,
+        }
+    }
+
+    /// CRC of `data`
+    pub fn checksum(&self, data: &[u8]) -> u32 {
+        let mut crc = CRC::new(*self);
+        crc.update(data);
+        crc.finalize()
+    }
+}
 
-pub struct CRC32 {
-    poly: u32,
-    ref_in: bool,
-    ref_out: bool,
-    initial: u32,
-    xor_out: u32,
+/// Table driven CRC engine, for any width from 8 to 32 bits
+///
+/// # Examples
+///
+/// ```
+/// use dfuse::checksum::{CRC, CRCParams};
+///
+/// let mut crc = CRC::new(CRCParams::CRC_32_ISO_HDLC);
+/// crc.update(b"123456789");
+/// assert_eq!(crc.finalize(), 0xCBF43926);
+/// ```
+#[derive(Clone)]
+pub struct CRC {
+    params: CRCParams,
 
     value: u32,
     table: [u32; 256],
 }
 
-impl CRC32 {
-    pub fn new_jam() -> CRC32 {
-        let crc = CRC32 {
-            poly: 0x04C11DB7,
-            ref_in: true,
-            ref_out: true,
-            initial: 0xFFFFFFFF,
-            xor_out: 0,
+impl CRC {
+    /// Panic if `params.width` is not between 8 and 32
+    pub fn new(params: CRCParams) -> CRC {
+        CRC::try_new(params).expect("unsupported CRC width")
+    }
+
+    /// `None` if `params.width` is not between 8 and 32
+    pub fn try_new(params: CRCParams) -> Option<CRC> {
+        if params.width < 8 || params.width > 32 {
+            return None;
+        }
+
+        let crc = CRC {
+            params,
             value: 0,
             table: [0u32; 256],
         };
 
-        crc._new()
+        Some(crc._new())
     }
 
-    pub fn _new(mut self) -> CRC32 {
+    pub fn new_jam() -> CRC {
+        CRC::new(CRCParams::CRC_32_JAMCRC)
+    }
+
+    pub fn new_iso_hdlc() -> CRC {
+        CRC::new(CRCParams::CRC_32_ISO_HDLC)
+    }
+
+    pub fn new_mpeg2() -> CRC {
+        CRC::new(CRCParams::CRC_32_MPEG_2)
+    }
+
+    pub fn params(&self) -> &CRCParams {
+        &self.params
+    }
+
+    fn _new(mut self) -> CRC {
         self._init_table();
         self.reset();
         self
     }
+
+    /// The register is kept aligned on its most significant bit
+    #[inline]
+    fn shift(&self) -> u8 {
+        32 - self.params.width
+    }
+
     #[inline]
     fn _init_table(&mut self) {
+        let poly = self.params.poly << self.shift();
+
         for i in 0u32..256u32 {
             let mut cur: u32 = i << 24;
 
             for _ in 0..8 {
                 if (cur & 0x80000000) != 0 {
                     cur <<= 1;
-                    cur ^= self.poly;
+                    cur ^= poly;
                 } else {
                     cur <<= 1;
                 }
@@ -77,20 +179,15 @@ impl CRC32 {
 
             self.table[i as usize] = cur;
         }
-
-        for b in self.table.iter() {
-            println!("{:X}", b)
-        }
-
     }
 
     pub fn reset(&mut self) {
-        self.value = self.initial;
+        self.value = self.params.initial << self.shift();
     }
 
     #[inline]
     pub fn add(&mut self, b: u8) {
-        let b: u8 = match self.ref_in {
+        let b: u8 = match self.params.ref_in {
             true => swap8(b),
             false => b,
         };
@@ -100,22 +197,39 @@ impl CRC32 {
         self.value = (self.value << 8) ^ (self.table[pos as usize]);
     }
 
+    pub fn update(&mut self, data: &[u8]) {
+        for b in data {
+            self.add(*b);
+        }
+    }
+
+    /// Add a 32 bit word, most significant byte first
+    ///
+    /// This is how the STM32 CRC unit processes the words written to it.
+    pub fn add_u32(&mut self, word: u32) {
+        for b in &word.to_be_bytes() {
+            self.add(*b);
+        }
+    }
+
+    /// CRC of the data added so far
     #[inline]
-    fn _finalize(&mut self) -> u32 {
-        let value = match self.ref_out {
-            true => swap32(self.value),
-            false => self.value,
+    pub fn value(&self) -> u32 {
+        let value = self.value >> self.shift();
+        let value = match self.params.ref_out {
+            true => swap(value, self.params.width),
+            false => value,
         };
 
-        value ^ self.xor_out
+        value ^ self.params.xor_out
     }
 
-    pub fn finalize(mut self) -> u32 {
-        self._finalize()
+    pub fn finalize(self) -> u32 {
+        self.value()
     }
 
     pub fn get_and_reset(&mut self) -> u32 {
-        let v = self._finalize();
+        let v = self.value();
         self.reset();
         v
     }
@@ -125,18 +239,59 @@ impl CRC32 {
 mod tests {
     use super::*;
 
-    static CRC_DEFAULT_CHECK: &'static str = "123456789";
+    static CRC_DEFAULT_CHECK: &str = "123456789";
 
-    fn crc_check(mut crc: CRC32) -> u32 {
+    fn crc_check(mut crc: CRC) -> u32 {
         for b in CRC_DEFAULT_CHECK.as_bytes() {
             crc.add(*b);
         }
         crc.finalize()
     }
+    #[test]
+    fn test_crc_reject_unsupported_width() {
+        let params = CRCParams {
+            width: 5,
+            ..CRCParams::CRC_16_CCITT
+        };
+        assert!(CRC::try_new(params).is_none());
+        assert!(CRC::try_new(CRCParams::CRC_16_CCITT).is_some());
+    }
+
     #[test]
     fn test_crc_jam_give_correct_value() {
-        let crc = CRC32::new_jam();
+        let crc = CRC::new_jam();
         assert_eq!(crc_check(crc), 0x340BC6D9);
     }
 
+    #[test]
+    fn test_crc_iso_hdlc_give_correct_value() {
+        let crc = CRC::new_iso_hdlc();
+        assert_eq!(crc_check(crc), 0xCBF43926);
+    }
+
+    #[test]
+    fn test_crc_mpeg2_give_correct_value() {
+        let crc = CRC::new_mpeg2();
+        assert_eq!(crc_check(crc), 0x0376E6E7);
+    }
+
+    #[test]
+    fn test_crc_bzip2_give_correct_value() {
+        let crc = CRC::new(CRCParams::CRC_32_BZIP2);
+        assert_eq!(crc_check(crc), 0xFC891918);
+    }
+
+    #[test]
+    fn test_crc_16_give_correct_value() {
+        assert_eq!(crc_check(CRC::new(CRCParams::CRC_16_CCITT)), 0x2189);
+        assert_eq!(crc_check(CRC::new(CRCParams::CRC_16_CCITT_FALSE)), 0x29B1);
+    }
+
+    #[test]
+    fn test_crc_stm32_words() {
+        let mut crc = CRC::new(CRCParams::STM32);
+        crc.add_u32(0x12345678);
+        assert_eq!(crc.get_and_reset(), 0xDF8A8A2B);
+        assert_eq!(crc.value(), 0xFFFFFFFF);
+    }
 }
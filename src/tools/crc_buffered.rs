@@ -12,18 +12,18 @@ use std::io::BufWriter;
 use std::io::Write;
 use std::io::Result;
 use byteorder::ByteOrder;
-use ::tools::CRC32;
+use ::tools::CRC;
 
 pub struct BufWriterWithCRC<W: Write> {
     buf: BufWriter<W>,
-    crc: CRC32,
+    crc: CRC,
 }
 
 impl<W: Write> BufWriterWithCRC<W> {
     pub fn new(inner: W) -> BufWriterWithCRC<W> {
         BufWriterWithCRC {
             buf: BufWriter::new(inner),
-            crc: CRC32::new_jam(),
+            crc: CRC::new_jam(),
         }
     }
 
@@ -33,11 +33,6 @@ impl<W: Write> BufWriterWithCRC<W> {
         T::write_u32(&mut buf, self.crc.get_and_reset());
         self.buf.write_all(&buf)
     }
-
-    #[inline]
-    pub fn reset_crc(&mut self) {
-        self.crc.reset();
-    }
 }
 
 impl<W: Write> Write for BufWriterWithCRC<W> {
@@ -45,14 +40,10 @@ impl<W: Write> Write for BufWriterWithCRC<W> {
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
         let res = self.buf.write(buf);
 
-        match &res {
-            &Ok(i) => {
-                for b in &buf[0..i] {
-                    self.crc.add(*b);
-                }
+        if let Ok(i) = res {
+            for b in &buf[0..i] {
+                self.crc.add(*b);
             }
-
-            _ => {}
         }
 
         res
@@ -0,0 +1,68 @@
+// Copyright © 2016 - Samuel Dolt <samuel@dolt.ch>
+//
+// See the COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms
+
+use std::io::Read;
+use std::io::Result;
+use byteorder::ByteOrder;
+use ::tools::CRC;
+
+/// Compute the CRC of everything read but the last 4 bytes,
+/// which are expected to be the stored CRC
+pub struct ReaderWithCRC<R: Read> {
+    inner: R,
+    crc: CRC,
+    tail: [u8; 4],
+    tail_len: usize,
+}
+
+impl<R: Read> ReaderWithCRC<R> {
+    pub fn new(inner: R) -> ReaderWithCRC<R> {
+        ReaderWithCRC {
+            inner,
+            crc: CRC::new_jam(),
+            tail: [0; 4],
+            tail_len: 0,
+        }
+    }
+
+    /// Return a tuple `(stored, computed)`, `None` if less than 4 bytes were read
+    pub fn crc<T: ByteOrder>(self) -> Option<(u32, u32)> {
+        if self.tail_len < 4 {
+            return None;
+        }
+        Some((T::read_u32(&self.tail), self.crc.finalize()))
+    }
+
+    #[inline]
+    fn push(&mut self, b: u8) {
+        if self.tail_len < 4 {
+            self.tail[self.tail_len] = b;
+            self.tail_len += 1;
+        } else {
+            self.crc.add(self.tail[0]);
+            self.tail = [self.tail[1], self.tail[2], self.tail[3], b];
+        }
+    }
+}
+
+impl<R: Read> Read for ReaderWithCRC<R> {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let res = self.inner.read(buf);
+
+        if let Ok(i) = res {
+            for b in &buf[0..i] {
+                self.push(*b);
+            }
+        }
+
+        res
+    }
+}
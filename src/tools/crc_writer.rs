@@ -0,0 +1,81 @@
+// Copyright © 2016 - Samuel Dolt <samuel@dolt.ch>
+//
+// See the COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms
+
+use std::io::Write;
+use std::io::Result;
+use ::tools::{CRC, CRCParams};
+
+/// Compute the CRC of everything written to the inner writer
+///
+/// # Examples
+///
+/// ```
+/// use std::io::Write;
+/// use dfuse::checksum::{CRCParams, WriterWithCRC};
+///
+/// let mut writer = WriterWithCRC::new(Vec::new(), CRCParams::CRC_32_ISO_HDLC);
+/// writer.write_all(b"123456789").unwrap();
+///
+/// assert_eq!(writer.crc(), 0xCBF43926);
+/// assert_eq!(writer.into_inner(), b"123456789");
+/// ```
+pub struct WriterWithCRC<W: Write> {
+    inner: W,
+    crc: CRC,
+}
+
+impl<W: Write> WriterWithCRC<W> {
+    pub fn new(inner: W, params: CRCParams) -> WriterWithCRC<W> {
+        WriterWithCRC {
+            inner,
+            crc: CRC::new(params),
+        }
+    }
+
+    /// CRC of the data written so far
+    pub fn crc(&self) -> u32 {
+        self.crc.value()
+    }
+
+    /// Start a new CRC, returning the previous one
+    pub fn reset_crc(&mut self) -> u32 {
+        self.crc.get_and_reset()
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for WriterWithCRC<W> {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let res = self.inner.write(buf);
+
+        if let Ok(i) = res {
+            self.crc.update(&buf[0..i]);
+        }
+
+        res
+    }
+
+    #[inline]
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
@@ -8,8 +8,20 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms
 
+#[cfg(feature = "std")]
 mod crc_buffered;
+#[cfg(feature = "std")]
 pub use self::crc_buffered::*;
 
+#[cfg(feature = "std")]
+mod crc_reader;
+#[cfg(feature = "std")]
+pub use self::crc_reader::*;
+
+#[cfg(feature = "std")]
+mod crc_writer;
+#[cfg(feature = "std")]
+pub use self::crc_writer::*;
+
 mod crc;
-pub use self::crc::*;
\ No newline at end of file
+pub use self::crc::*;
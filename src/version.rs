@@ -0,0 +1,179 @@
+// Copyright © 2016 - Samuel Dolt <samuel@dolt.ch>
+//
+// See the COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use core::fmt;
+use core::str::FromStr;
+
+/// A firmware version, stored as BCD in the `bcdDevice` field of the suffix
+///
+/// The layout is `0xMMmp`: two digits of major, one of minor, one of patch,
+/// so 2.2.0 is `0x0220`.
+///
+/// # Examples
+///
+/// ```
+/// use dfuse::BcdVersion;
+///
+/// let version = BcdVersion::new(2, 2, 0).unwrap();
+/// assert_eq!(version.to_bcd(), 0x0220);
+/// assert_eq!(BcdVersion::from_bcd(0x1234), BcdVersion::new(12, 3, 4));
+/// assert!(BcdVersion::new(2, 10, 0).is_none());
+///
+/// let version: BcdVersion = "2.3.1".parse().unwrap();
+/// assert_eq!(version.to_bcd(), 0x0231);
+/// assert_eq!(version.to_string(), "2.3.1");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BcdVersion {
+    major: u8,
+    minor: u8,
+    patch: u8,
+}
+
+impl BcdVersion {
+    /// `None` if `major` is above 99, or `minor` or `patch` above 9
+    pub fn new(major: u8, minor: u8, patch: u8) -> Option<BcdVersion> {
+        if major > 99 || minor > 9 || patch > 9 {
+            return None;
+        }
+
+        Some(BcdVersion {
+            major,
+            minor,
+            patch,
+        })
+    }
+
+    /// `None` if a digit of `bcd` is above 9
+    pub fn from_bcd(bcd: u16) -> Option<BcdVersion> {
+        let digit = |n: u16| (bcd >> (4 * n) & 0xF) as u8;
+        if (0..4).any(|n| digit(n) > 9) {
+            return None;
+        }
+
+        BcdVersion::new(10 * digit(3) + digit(2), digit(1), digit(0))
+    }
+
+    pub fn major(&self) -> u8 {
+        self.major
+    }
+
+    pub fn minor(&self) -> u8 {
+        self.minor
+    }
+
+    pub fn patch(&self) -> u8 {
+        self.patch
+    }
+
+    pub fn to_bcd(&self) -> u16 {
+        (self.major as u16 / 10) << 12 | (self.major as u16 % 10) << 8 |
+        (self.minor as u16) << 4 | self.patch as u16
+    }
+}
+
+impl fmt::Display for BcdVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Why a string is not a `BcdVersion`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseVersionError {
+    /// Not `major.minor` or `major.minor.patch` decimal numbers
+    Invalid,
+    /// A number doesn't fit: major up to 99, minor and patch up to 9
+    OutOfRange,
+}
+
+impl fmt::Display for ParseVersionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseVersionError::Invalid => f.write_str("version is not major.minor[.patch]"),
+            ParseVersionError::OutOfRange => {
+                f.write_str("version doesn't fit BCD, major is up to 99, minor and patch up to 9")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl ::std::error::Error for ParseVersionError {}
+
+impl FromStr for BcdVersion {
+    type Err = ParseVersionError;
+
+    /// Parse `major.minor` or `major.minor.patch`, as in a Cargo version
+    ///
+    /// Pre-release and build metadata are rejected, they can't be stored.
+    fn from_str(txt: &str) -> Result<BcdVersion, ParseVersionError> {
+        let mut numbers = [0u8; 3];
+        let mut count = 0;
+        for part in txt.split('.') {
+            if count == numbers.len() || part.is_empty() || !part.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(ParseVersionError::Invalid);
+            }
+            numbers[count] = part.parse().map_err(|_| ParseVersionError::OutOfRange)?;
+            count += 1;
+        }
+        if count < 2 {
+            return Err(ParseVersionError::Invalid);
+        }
+
+        BcdVersion::new(numbers[0], numbers[1], numbers[2]).ok_or(ParseVersionError::OutOfRange)
+    }
+}
+
+impl From<BcdVersion> for u16 {
+    fn from(version: BcdVersion) -> u16 {
+        version.to_bcd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bcd_layout() {
+        assert_eq!(BcdVersion::new(0, 0, 0).unwrap().to_bcd(), 0x0000);
+        assert_eq!(BcdVersion::new(2, 2, 0).unwrap().to_bcd(), 0x0220);
+        assert_eq!(BcdVersion::new(99, 9, 9).unwrap().to_bcd(), 0x9999);
+        assert_eq!(u16::from(BcdVersion::new(12, 3, 4).unwrap()), 0x1234);
+        assert!(BcdVersion::new(100, 0, 0).is_none());
+        assert!(BcdVersion::new(1, 0, 10).is_none());
+    }
+
+    #[test]
+    fn from_bcd() {
+        for bcd in [0x0000, 0x0220, 0x1234, 0x9999] {
+            assert_eq!(BcdVersion::from_bcd(bcd).unwrap().to_bcd(), bcd);
+        }
+        assert!(BcdVersion::from_bcd(0xFFFF).is_none());
+        assert!(BcdVersion::from_bcd(0x01A0).is_none());
+    }
+
+    #[test]
+    fn parse_and_display() {
+        let parse = |txt: &str| txt.parse::<BcdVersion>();
+        assert_eq!(parse("2.3.1").unwrap().to_bcd(), 0x0231);
+        assert_eq!(parse("2.3").unwrap().to_bcd(), 0x0230);
+        assert_eq!(parse("99.9.9").unwrap().to_string(), "99.9.9");
+        assert_eq!(parse("02.03").unwrap().to_string(), "2.3.0");
+
+        for txt in ["", "2", "2.", ".3", "2.3.1.0", "2.3.1-rc1", "v2.3", "+2.3", "2.x"] {
+            assert_eq!(parse(txt), Err(ParseVersionError::Invalid), "{:?}", txt);
+        }
+        for txt in ["100.0", "2.10", "2.3.10", "2.3.300", "2.3.99999999999"] {
+            assert_eq!(parse(txt), Err(ParseVersionError::OutOfRange), "{:?}", txt);
+        }
+    }
+}
@@ -9,10 +9,9 @@
 // except according to those terms.
 
 extern crate dfuse;
-extern crate byteorder;
 
 use dfuse::DfuseFile;
-use byteorder::{BigEndian, LittleEndian, WriteBytesExt};
+use dfuse::option_bytes::{OptionBytes, Stm32F0};
 
 
 #[test]
@@ -27,14 +26,12 @@ fn create_empty_file() {
     // He has a STM32F042 based device, a minimual dfu file would
     // a file that change device's option byte
 
-    // He found default option byte value  and start adress in RM0091 p.75 to 78
+    // He found default option byte value  and start adress in RM0091 p.75 to 78,
+    // the crate already knows them
 
-    let adress = 0x1FFFF800u32;
-    let ob_word: Vec<u32> = vec![0x00FF55AA, 0x00FF00FF, 0x00FF00FF, 0x00FF00FF];
-    let mut ob: Vec<u8> = Vec::new();
-    for word in ob_word {
-        ob.write_u32::<LittleEndian>(word);
-    }
+    let adress = Stm32F0::ADRESS;
+    let ob = Stm32F0::default().to_bytes();
+    assert_eq!(&ob[0..4], &[0xAA, 0x55, 0xFF, 0x00]);
 
 
     // He know that option byte for an STM32F042 can be readed or writed
@@ -60,5 +57,12 @@ fn create_empty_file() {
 
     file.write_to(&mut real_file).unwrap();
 
+    let reference = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"),
+                                          "/tests/golden/stm32f042_option_bytes.dfu"))
+        .unwrap();
+    assert_eq!(DfuseFile::read_from(&mut &reference[..]).unwrap(), file);
 
+    let mut written = vec![];
+    file.write_to(&mut written).unwrap();
+    assert!(written == reference);
 }
\ No newline at end of file
@@ -0,0 +1,127 @@
+// Copyright © 2016 - Samuel Dolt <samuel@dolt.ch>
+//
+// See the COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Reference files of `tests/golden`, written by `generate.py` from the
+//! documented layout
+//!
+//! Every file is read and compared to the file it should hold, and to its
+//! report in the `.txt` file next to it. Every file written must match the
+//! reference byte for byte.
+
+extern crate dfuse;
+
+use std::fs;
+use std::path::PathBuf;
+
+use dfuse::codec::SliceReader;
+use dfuse::{DfuseFile, Image, ImageElement, Prefix};
+
+fn path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden").join(name)
+}
+
+fn golden(name: &str) -> Vec<u8> {
+    fs::read(path(name)).unwrap()
+}
+
+fn stm32f042_option_bytes() -> DfuseFile {
+    let mut ob = vec![0xAA, 0x55];
+    for _ in 0..7 {
+        ob.extend_from_slice(&[0xFF, 0x00]);
+    }
+
+    let mut file = DfuseFile::new();
+    file.add_image("Option Bytes  ", 1, 0x1FFFF800, ob);
+    file.set_vendor_id(0x0483);
+    file.set_product_id(0xDF11);
+    file.set_version(0x2200);
+    file
+}
+
+fn stm32f405_firmware() -> DfuseFile {
+    let mut file = DfuseFile::new();
+    file.add_image("Internal Flash", 0, 0x08000000, (0..64).collect());
+    file.images_mut()[0].elements.push(ImageElement::new(0x08004000, b"DfuSe reference".to_vec()));
+    file.add_unamed_image(1,
+                          0x1FFFC000,
+                          vec![0xAA, 0xEC, 0x55, 0x13, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x0F, 0x00, 0xF0,
+                               0xFF, 0xFF, 0xFF, 0xFF]);
+    file.set_vendor_id(0x0483);
+    file.set_product_id(0xDF11);
+    file.set_version(0x0220);
+    file
+}
+
+fn unnamed_vendor_data() -> DfuseFile {
+    let mut file = DfuseFile::new();
+    file.images_mut().push(Image {
+        name: None,
+        alternate: 0,
+        elements: vec![ImageElement::new(0x08000000, vec![0x01, 0x02, 0x03, 0x04])],
+    });
+    file.set_vendor_id(0x1209);
+    file.set_product_id(0x0001);
+    file.set_version(0x0100);
+    file.suffix_mut().vendor_data = b"ACME".to_vec();
+    file
+}
+
+/// Reference files and what they hold
+fn corpus() -> Vec<(&'static str, DfuseFile)> {
+    vec![("empty.dfu", DfuseFile::new()),
+         ("stm32f042_option_bytes.dfu", stm32f042_option_bytes()),
+         ("stm32f405_firmware.dfu", stm32f405_firmware()),
+         ("unnamed_vendor_data.dfu", unnamed_vendor_data())]
+}
+
+fn assert_written(name: &str, file: &DfuseFile) {
+    let mut buf = vec![];
+    file.write_to(&mut buf).unwrap();
+    assert!(buf == golden(name), "{} is not written as the reference", name);
+    assert_eq!(file.to_bytes().unwrap(), buf);
+}
+
+#[test]
+fn read_reference_files() {
+    for (name, expected) in corpus() {
+        let buf = golden(name);
+
+        let (file, crc) = DfuseFile::read_with_crc(&mut &buf[..]).unwrap();
+        assert!(crc.is_valid(), "{}", name);
+        assert_eq!(file, expected, "{}", name);
+        assert_eq!(DfuseFile::from_bytes(&buf).unwrap(), (file.clone(), crc));
+
+        let prefix = Prefix::decode(&mut SliceReader::new(&buf)).unwrap();
+        assert_eq!(prefix.image_size() as usize, buf.len());
+
+        let report = fs::read_to_string(path(&name.replace(".dfu", ".txt"))).unwrap();
+        assert_eq!(file.report().crc(crc).to_string(), report, "{}", name);
+    }
+}
+
+#[test]
+fn write_empty() {
+    assert_written("empty.dfu", &DfuseFile::new());
+}
+
+#[test]
+fn write_unnamed_vendor_data() {
+    assert_written("unnamed_vendor_data.dfu", &unnamed_vendor_data());
+}
+
+#[test]
+fn write_stm32f042_option_bytes() {
+    assert_written("stm32f042_option_bytes.dfu", &stm32f042_option_bytes());
+}
+
+#[test]
+fn write_stm32f405_firmware() {
+    assert_written("stm32f405_firmware.dfu", &stm32f405_firmware());
+}
@@ -0,0 +1,114 @@
+#!/usr/bin/env python3
+# Copyright © 2016 - Samuel Dolt <samuel@dolt.ch>
+#
+# See the COPYRIGHT file at the top-level directory of this distribution.
+#
+# Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+# http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+# <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+# option. This file may not be copied, modified, or distributed
+# except according to those terms.
+
+"""Write the reference files of tests/golden.rs
+
+The files are built from the DfuSe file format of UM0391 ("DfuSe File Format
+Specification", Rev 1) and the DFU suffix of the USB DFU 1.1 specification,
+the layout written by ST's DfuFileMgr, without using the crate. Every
+multi-byte field is little endian.
+"""
+
+import os
+import struct
+import zlib
+
+
+def element(address, data):
+    """Image element of UM0391"""
+    return (
+        struct.pack('<I', address)      # dwElementAddress
+        + struct.pack('<I', len(data))  # dwElementSize, data only
+        + data                          # Data
+    )
+
+
+def target(alternate, name, elements):
+    """Target prefix of UM0391, followed by its image elements"""
+    body = b''.join(elements)
+    return (
+        b'Target'                                # szSignature
+        + struct.pack('<B', alternate)           # bAlternateSetting
+        + struct.pack('<I', name is not None)    # bTargetNamed, a 4 byte boolean
+        + (name or b'').ljust(255, b'\0')        # szTargetName, 255 bytes
+        + struct.pack('<I', len(body))           # dwTargetSize, elements only
+        + struct.pack('<I', len(elements))       # dwNbElements
+        + body
+    )
+
+
+def suffix(vid, pid, version, vendor_data):
+    """DFU suffix of UM0391, from DFU 1.1 appendix B, with bcdDFU 0x011A
+
+    Vendor data goes before the standard fields, bLength counting it.
+    """
+    return (
+        vendor_data
+        + struct.pack('<H', version)                 # bcdDevice
+        + struct.pack('<H', pid)                     # idProduct
+        + struct.pack('<H', vid)                     # idVendor
+        + struct.pack('<H', 0x011A)                  # bcdDFU
+        + b'UFD'                                     # ucDfuSignature
+        + struct.pack('<B', len(vendor_data) + 16)   # bLength, dwCRC included
+    )
+
+
+def dfuse(targets, vid=0xFFFF, pid=0xFFFF, version=0xFFFF, vendor_data=b''):
+    """DFU prefix of UM0391, the images, then the DFU suffix"""
+    tail = suffix(vid, pid, version, vendor_data)
+    # UM0391 defines DFUImageSize as the total DFU file length in bytes: the
+    # prefix, the images and the suffix, dwCRC included
+    image_size = 11 + sum(len(t) for t in targets) + len(tail) + 4
+    content = (
+        b'DfuSe'                            # szSignature
+        + struct.pack('<B', 0x01)           # bVersion
+        + struct.pack('<I', image_size)     # DFUImageSize
+        + struct.pack('<B', len(targets))   # bTargets
+        + b''.join(targets)
+        + tail
+    )
+    # dwCRC covers the whole file but itself (DFU 1.1 appendix B). It's the
+    # CRC-32 of zlib without its final XOR, CRC-32/JAMCRC.
+    crc = zlib.crc32(content) ^ 0xFFFFFFFF
+    return content + struct.pack('<I', crc)
+
+
+FILES = {
+    'empty.dfu': dfuse([]),
+
+    # tests/create_dfufile.rs
+    'stm32f042_option_bytes.dfu': dfuse(
+        [target(1, b'Option Bytes  ', [
+            element(0x1FFFF800, bytes([0xAA, 0x55] + [0xFF, 0x00] * 7)),
+        ])],
+        vid=0x0483, pid=0xDF11, version=0x2200),
+
+    'stm32f405_firmware.dfu': dfuse(
+        [target(0, b'Internal Flash', [
+            element(0x08000000, bytes(range(64))),
+            element(0x08004000, b'DfuSe reference'),
+        ]),
+         target(1, None, [
+             element(0x1FFFC000, bytes([0xAA, 0xEC, 0x55, 0x13, 0xFF, 0xFF, 0xFF, 0xFF,
+                                        0xFF, 0x0F, 0x00, 0xF0, 0xFF, 0xFF, 0xFF, 0xFF])),
+         ])],
+        vid=0x0483, pid=0xDF11, version=0x0220),
+
+    'unnamed_vendor_data.dfu': dfuse(
+        [target(0, None, [element(0x08000000, b'\x01\x02\x03\x04')])],
+        vid=0x1209, pid=0x0001, version=0x0100, vendor_data=b'ACME'),
+}
+
+if __name__ == '__main__':
+    here = os.path.dirname(os.path.abspath(__file__))
+    for name, data in FILES.items():
+        with open(os.path.join(here, name), 'wb') as f:
+            f.write(data)
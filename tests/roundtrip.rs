@@ -0,0 +1,77 @@
+// Copyright © 2016 - Samuel Dolt <samuel@dolt.ch>
+//
+// See the COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate dfuse;
+extern crate proptest;
+
+use dfuse::{DfuseFile, Image, ImageElement, Suffix};
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+fn element() -> impl Strategy<Value = ImageElement> {
+    (any::<u32>(), vec(any::<u8>(), 0..64)).prop_map(|(start, data)| ImageElement::new(start, data))
+}
+
+fn image() -> impl Strategy<Value = Image> {
+    // Names are stored in 255 bytes, up to the first NUL
+    let name = proptest::option::of("[ -~]{0,255}");
+    (name, any::<u8>(), vec(element(), 0..4)).prop_map(|(name, alternate, elements)| {
+        Image {
+            name,
+            alternate,
+            elements,
+        }
+    })
+}
+
+fn file() -> impl Strategy<Value = DfuseFile> {
+    let suffix = (any::<u16>(), any::<u16>(), any::<u16>(), vec(any::<u8>(), 0..=Suffix::MAX_LENGTH - 16));
+    (vec(image(), 0..4), suffix).prop_map(|(images, (fw_version, usb_pid, usb_vid, vendor_data))| {
+        let mut file = DfuseFile::new();
+        *file.images_mut() = images;
+        *file.suffix_mut() = Suffix {
+            fw_version,
+            usb_pid,
+            usb_vid,
+            vendor_data,
+        };
+        file
+    })
+}
+
+proptest! {
+    #[test]
+    fn read_what_was_written(file in file()) {
+        let mut buf = Vec::new();
+        file.write_to(&mut buf).unwrap();
+
+        prop_assert_eq!(buf.len(), file.size());
+        prop_assert_eq!(&DfuseFile::read_from(&mut &buf[..]).unwrap(), &file);
+    }
+
+    #[test]
+    fn decode_what_was_encoded(file in file()) {
+        let buf = file.to_bytes().unwrap();
+        prop_assert_eq!(buf.len(), file.size());
+
+        let (read, crc) = DfuseFile::from_bytes(&buf).unwrap();
+        prop_assert!(crc.is_valid());
+        prop_assert_eq!(read, file);
+    }
+
+    #[test]
+    fn reject_truncated(file in file(), cut in any::<prop::sample::Index>()) {
+        let buf = file.to_bytes().unwrap();
+        let len = cut.index(buf.len());
+
+        prop_assert!(DfuseFile::from_bytes(&buf[..len]).is_err());
+        prop_assert!(DfuseFile::read_from(&mut &buf[..len]).is_err());
+    }
+}